@@ -1,11 +1,22 @@
-use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
+mod mount;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use easy_fs::{
+    block_cache_set_capacity, block_cache_set_policy, block_cache_stats, fsck, BlockDevice,
+    EasyFileSystem, Inode, WritePolicy,
+};
+use fuser::MountOption;
+use mount::EasyFuse;
 use std::fs::{read_dir, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Mutex;
 
 const BLOCK_SZ: usize = 512;
+const BLOCK_BITS: usize = BLOCK_SZ * 8;
+/// Blocks cached when packing or mounting, 2 MiB.
+const CACHE_BLOCKS: usize = 4096;
 
 struct BlockFile(Mutex<File>);
 
@@ -30,10 +41,9 @@ impl BlockDevice for BlockFile {
 }
 
 fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
-}
-
-fn easy_fs_pack() -> std::io::Result<()> {
+    let image = || Arg::with_name("image").required(true).help("easy-fs image");
+    let path = |help| Arg::with_name("path").required(true).help(help);
+    let host_file = |help| Arg::with_name("host_file").required(true).help(help);
     let matches = App::new("EasyFileSystem packer")
         .arg(
             Arg::with_name("source")
@@ -49,107 +59,1020 @@ fn easy_fs_pack() -> std::io::Result<()> {
                 .takes_value(true)
                 .help("Executable target dir(with backslash)"),
         )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .default_value("32")
+                .help("Image size in MiB"),
+        )
+        .arg(
+            Arg::with_name("inodes")
+                .long("inodes")
+                .takes_value(true)
+                .default_value("4095")
+                .help("Number of files and directories the image can hold"),
+        )
+        .arg(
+            Arg::with_name("data")
+                .short("d")
+                .long("data")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Dir of data files packed under their own names, may be repeated"),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check an easy-fs image")
+                .arg(
+                    Arg::with_name("repair")
+                        .short("r")
+                        .long("repair")
+                        .help("Fix the problems that can be fixed"),
+                )
+                .arg(image()),
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("List a directory of an image")
+                .arg(image())
+                .arg(Arg::with_name("path").default_value("/")),
+        )
+        .subcommand(
+            SubCommand::with_name("cat")
+                .about("Print a file of an image")
+                .arg(image())
+                .arg(path("File to print")),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Copy a file out of an image")
+                .arg(image())
+                .arg(path("File to copy"))
+                .arg(host_file("Destination on the host")),
+        )
+        .subcommand(
+            SubCommand::with_name("put")
+                .about("Copy a host file into an image, replacing the content of an existing file")
+                .arg(image())
+                .arg(host_file("Host file to copy"))
+                .arg(path("Destination in the image")),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Remove a file or an empty directory from an image")
+                .arg(image())
+                .arg(path("File to remove")),
+        )
+        .subcommand(
+            SubCommand::with_name("stat")
+                .about("Print the superblock and usage of an image")
+                .arg(image()),
+        )
+        .subcommand(
+            SubCommand::with_name("mount")
+                .about("Mount an image with FUSE until it is unmounted")
+                .arg(image())
+                .arg(
+                    Arg::with_name("mountpoint")
+                        .required(true)
+                        .help("Directory to mount on"),
+                ),
+        )
         .get_matches();
+    let result = match matches.subcommand() {
+        ("fsck", Some(matches)) => {
+            std::process::exit(easy_fs_fsck(matches).expect("Error when checking easy-fs!"))
+        }
+        ("ls", Some(matches)) => easy_fs_ls(matches),
+        ("cat", Some(matches)) => easy_fs_cat(matches),
+        ("get", Some(matches)) => easy_fs_get(matches),
+        ("put", Some(matches)) => easy_fs_put(matches),
+        ("rm", Some(matches)) => easy_fs_rm(matches),
+        ("stat", Some(matches)) => easy_fs_stat(matches),
+        ("mount", Some(matches)) => easy_fs_mount(matches),
+        _ => easy_fs_pack(&matches),
+    };
+    if let Err(err) = result {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn open_block_file(matches: &ArgMatches) -> std::io::Result<Arc<BlockFile>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(matches.value_of("image").unwrap())?;
+    Ok(Arc::new(BlockFile(Mutex::new(file))))
+}
+
+/// Open the image given by the `image` argument and return its root directory.
+fn open_image(matches: &ArgMatches) -> std::io::Result<Arc<Inode>> {
+    let efs = EasyFileSystem::open(open_block_file(matches)?);
+    Ok(Arc::new(EasyFileSystem::root_inode(&efs)))
+}
+
+/// Walk `path` from the root directory.
+fn find_inode(root_inode: &Arc<Inode>, path: &str) -> std::io::Result<Arc<Inode>> {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .try_fold(root_inode.clone(), |inode, name| inode.find(name))
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{}: not found", path)))
+}
+
+/// Return the parent directory of `path` and the last name in it.
+fn find_parent<'a>(
+    root_inode: &Arc<Inode>,
+    path: &'a str,
+) -> std::io::Result<(Arc<Inode>, &'a str)> {
+    let (parent, name) = path
+        .trim_end_matches('/')
+        .rsplit_once('/')
+        .unwrap_or(("", path));
+    Ok((find_inode(root_inode, parent)?, name))
+}
+
+fn read_all(inode: &Inode) -> Vec<u8> {
+    let mut data = vec![0u8; inode.metadata().size as usize];
+    let len = inode.read_at(0, &mut data);
+    data.truncate(len);
+    data
+}
+
+fn easy_fs_ls(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches)?;
+    let dir = find_inode(&root_inode, matches.value_of("path").unwrap())?;
+    if !dir.is_dir() {
+        return Err(Error::new(ErrorKind::Other, "not a directory"));
+    }
+    for entry in dir.read_dir(0) {
+        let metadata = dir.find(&entry.name).unwrap().metadata();
+        println!(
+            "{}{:03o} {:5} {:3} {:10} {}",
+            if metadata.is_dir { 'd' } else { '-' },
+            metadata.mode,
+            metadata.inode_id,
+            metadata.nlink,
+            metadata.size,
+            entry.name
+        );
+    }
+    Ok(())
+}
+
+fn easy_fs_cat(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches)?;
+    let inode = find_inode(&root_inode, matches.value_of("path").unwrap())?;
+    std::io::stdout().write_all(&read_all(&inode))
+}
+
+fn easy_fs_get(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches)?;
+    let inode = find_inode(&root_inode, matches.value_of("path").unwrap())?;
+    if inode.is_dir() {
+        return Err(Error::new(ErrorKind::Other, "is a directory"));
+    }
+    File::create(matches.value_of("host_file").unwrap())?.write_all(&read_all(&inode))
+}
+
+fn easy_fs_put(matches: &ArgMatches) -> std::io::Result<()> {
+    let mut data = Vec::new();
+    File::open(matches.value_of("host_file").unwrap())?.read_to_end(&mut data)?;
+    let root_inode = open_image(matches)?;
+    let (parent, name) = find_parent(&root_inode, matches.value_of("path").unwrap())?;
+    let inode = match parent.find(name) {
+        Some(inode) if inode.is_dir() => {
+            return Err(Error::new(ErrorKind::Other, "is a directory"));
+        }
+        Some(inode) => {
+            inode.clear();
+            inode
+        }
+        None => parent
+            .create(name)
+            .ok_or_else(|| Error::new(ErrorKind::Other, format!("cannot create {}", name)))?,
+    };
+    inode.write_at(0, &data);
+    Ok(())
+}
+
+fn easy_fs_rm(matches: &ArgMatches) -> std::io::Result<()> {
+    let root_inode = open_image(matches)?;
+    let path = matches.value_of("path").unwrap();
+    let (parent, name) = find_parent(&root_inode, path)?;
+    let inode = find_inode(&root_inode, path)?;
+    let removed = if inode.is_dir() {
+        parent.rmdir(name)
+    } else {
+        parent.unlink(name)
+    };
+    if removed {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Other,
+            format!("cannot remove {}", path),
+        ))
+    }
+}
+
+fn easy_fs_stat(matches: &ArgMatches) -> std::io::Result<()> {
+    let efs = EasyFileSystem::open(open_block_file(matches)?);
+    let efs = efs.lock();
+    let super_block = efs.super_block();
+    let (inodes, data_blocks) = efs.usage();
+    println!("{:#?}", super_block);
+    println!("dirent format: {:?}", efs.dirent_format());
+    println!(
+        "journal: {}",
+        match efs.journal() {
+            Some(journal) => format!("blocks {:?}", journal.region()),
+            None => String::from("none"),
+        }
+    );
+    println!(
+        "inodes: {} used, {} total",
+        inodes,
+        super_block.inode_bitmap_blocks as usize * BLOCK_SZ * 8
+    );
+    println!(
+        "data blocks: {} used, {} total",
+        data_blocks, super_block.data_area_blocks
+    );
+    Ok(())
+}
+
+/// Serve the image until `fusermount -u` or `umount`.
+fn easy_fs_mount(matches: &ArgMatches) -> std::io::Result<()> {
+    block_cache_set_capacity(CACHE_BLOCKS);
+    block_cache_set_policy(WritePolicy::WriteBack);
+    let efs = EasyFileSystem::open(open_block_file(matches)?);
+    fuser::mount2(
+        EasyFuse::new(efs),
+        matches.value_of("mountpoint").unwrap(),
+        &[
+            MountOption::FSName(String::from("easy-fs")),
+            MountOption::DefaultPermissions,
+        ],
+    )
+}
+
+/// Return 0 if the image is clean, 1 if all the problems were fixed, 4 otherwise.
+fn easy_fs_fsck(matches: &ArgMatches) -> std::io::Result<i32> {
+    let image = matches.value_of("image").unwrap();
+    let repair = matches.is_present("repair");
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new().read(true).write(true).open(image)?,
+    )));
+    let problems = fsck(block_file, repair);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: clean", image);
+        Ok(0)
+    } else if repair && problems.iter().all(|problem| problem.is_repairable()) {
+        println!("{}: {} problems fixed", image, problems.len());
+        Ok(1)
+    } else {
+        println!("{}: {} problems found", image, problems.len());
+        Ok(4)
+    }
+}
+
+fn parse_arg(matches: &ArgMatches, name: &str) -> std::io::Result<u32> {
+    let value = matches.value_of(name).unwrap();
+    value.parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid --{}: {}", name, value),
+        )
+    })
+}
+
+/// Files to pack as (name in the image, host path), the apps first.
+fn pack_list(matches: &ArgMatches) -> std::io::Result<Vec<(String, PathBuf)>> {
+    let src_path = matches.value_of("source").unwrap();
+    let target_path = matches.value_of("target").unwrap();
+    let mut files = Vec::new();
+    for dir_entry in read_dir(src_path)? {
+        let name_with_ext = dir_entry?.file_name().into_string().unwrap();
+        let app = name_with_ext.split('.').next().unwrap().to_string();
+        let path = PathBuf::from(format!("{}{}", target_path, app));
+        files.push((app, path));
+    }
+    for data_path in matches.values_of("data").into_iter().flatten() {
+        for dir_entry in read_dir(data_path)? {
+            let dir_entry = dir_entry?;
+            if dir_entry.file_type()?.is_file() {
+                let name = dir_entry.file_name().into_string().map_err(|name| {
+                    Error::new(ErrorKind::InvalidData, format!("bad name {:?}", name))
+                })?;
+                files.push((name, dir_entry.path()));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// The files are packed sorted by name and the inode timestamps stay 0,
+/// so the same inputs always give a byte-identical image.
+fn easy_fs_pack(matches: &ArgMatches) -> std::io::Result<()> {
     let src_path = matches.value_of("source").unwrap();
     let target_path = matches.value_of("target").unwrap();
     println!("src_path = {}\ntarget_path = {}", src_path, target_path);
+    let total_blocks = parse_arg(matches, "size")? * 2048;
+    let inodes = parse_arg(matches, "inodes")?;
+    // the root takes an inode too
+    let inode_bitmap_blocks = (inodes as usize + 1 + BLOCK_BITS - 1) / BLOCK_BITS;
+    if total_blocks < EasyFileSystem::min_blocks(inode_bitmap_blocks as u32) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} MiB are too few for {} inodes",
+                total_blocks / 2048,
+                inodes
+            ),
+        ));
+    }
+    let mut files = pack_list(matches)?;
+    files.sort();
+    if let Some(pair) = files.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} is packed twice", pair[0].0),
+        ));
+    }
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(true)
             .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(32 * 2048 * 512).unwrap();
+        f.set_len(total_blocks as u64 * BLOCK_SZ as u64)?;
         f
     })));
-    // 32MiB, at most 4095 files
-    let efs = EasyFileSystem::create(block_file, 32 * 2048, 1);
+    block_cache_set_capacity(CACHE_BLOCKS);
+    block_cache_set_policy(WritePolicy::WriteBack);
+    let efs = EasyFileSystem::create(block_file, total_blocks, inode_bitmap_blocks as u32);
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
-    let apps: Vec<_> = read_dir(src_path)
-        .unwrap()
-        .into_iter()
-        .map(|dir_entry| {
-            let mut name_with_ext = dir_entry.unwrap().file_name().into_string().unwrap();
-            name_with_ext.drain(name_with_ext.find('.').unwrap()..name_with_ext.len());
-            name_with_ext
-        })
-        .collect();
-    for app in apps {
+    for (name, path) in files {
         // load app data from host file system
-        let mut host_file = File::open(format!("{}{}", target_path, app)).unwrap();
+        let mut host_file = File::open(&path)
+            .map_err(|err| Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
         let mut all_data: Vec<u8> = Vec::new();
-        host_file.read_to_end(&mut all_data).unwrap();
+        host_file.read_to_end(&mut all_data)?;
         // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
+        let inode = root_inode
+            .create(&name)
+            .ok_or_else(|| Error::new(ErrorKind::Other, format!("cannot create {}", name)))?;
         // write data to easy-fs
         inode.write_at(0, all_data.as_slice());
     }
-    // list apps
-    // for app in root_inode.ls() {
-    //     println!("{}", app);
-    // }
+    efs.lock().sync();
+    let stats = block_cache_stats();
+    println!(
+        "block cache: {} hits, {} misses, {} evictions",
+        stats.hits, stats.misses, stats.evictions
+    );
     Ok(())
 }
 
-#[test]
-fn efs_test() -> std::io::Result<()> {
-    let block_file = Arc::new(BlockFile(Mutex::new({
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use easy_fs::{DirEntryFormat, FsckProblem};
+    use std::os::unix::fs::MetadataExt;
+
+    /// The block cache is shared by the tests, so they run one at a time.
+    static CACHE_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Take `CACHE_LOCK` and reset the block cache to the defaults.
+    fn lock_cache() -> std::sync::MutexGuard<'static, ()> {
+        let guard = CACHE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        block_cache_set_capacity(16);
+        block_cache_set_policy(WritePolicy::WriteThrough);
+        guard
+    }
+
+    /// Create an image file of `blocks` blocks at `path`.
+    fn test_image(path: &str, blocks: u64) -> std::io::Result<Arc<BlockFile>> {
         let f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open("target/fs.img")?;
-        f.set_len(8192 * 512).unwrap();
-        f
-    })));
-    EasyFileSystem::create(block_file.clone(), 4096, 1);
-    let efs = EasyFileSystem::open(block_file.clone());
-    let root_inode = EasyFileSystem::root_inode(&efs);
-    root_inode.create("filea");
-    root_inode.create("fileb");
-    for name in root_inode.ls() {
-        println!("{}", name);
-    }
-    let filea = root_inode.find("filea").unwrap();
-    let greet_str = "Hello, world!";
-    filea.write_at(0, greet_str.as_bytes());
-    //let mut buffer = [0u8; 512];
-    let mut buffer = [0u8; 233];
-    let len = filea.read_at(0, &mut buffer);
-    assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap(),);
-
-    let mut random_str_test = |len: usize| {
-        filea.clear();
-        assert_eq!(filea.read_at(0, &mut buffer), 0,);
-        let mut str = String::new();
-        use rand;
-        // random digit
-        for _ in 0..len {
-            str.push(char::from('0' as u8 + rand::random::<u8>() % 10));
+            .truncate(true)
+            .open(path)?;
+        f.set_len(blocks * BLOCK_SZ as u64)?;
+        Ok(Arc::new(BlockFile(Mutex::new(f))))
+    }
+
+    /// The image as it is on the file, without the block cache.
+    fn read_image(block_file: &BlockFile) -> std::io::Result<Vec<u8>> {
+        let mut image = Vec::new();
+        let mut file = block_file.0.lock().unwrap();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut image)?;
+        Ok(image)
+    }
+
+    fn block(image: &[u8], id: usize) -> Vec<u8> {
+        image[id * BLOCK_SZ..(id + 1) * BLOCK_SZ].to_vec()
+    }
+
+    /// A device on the same file with nothing in the block cache yet.
+    fn reopen(block_file: &BlockFile) -> std::io::Result<Arc<dyn BlockDevice>> {
+        Ok(Arc::new(BlockFile(Mutex::new(
+            block_file.0.lock().unwrap().try_clone()?,
+        ))))
+    }
+
+    #[test]
+    fn efs_test() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/fs.img", 8192)?;
+        let efs = EasyFileSystem::create(block_file.clone(), 8192, 1);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        root_inode.create("filea");
+        root_inode.create("fileb");
+        for name in root_inode.ls() {
+            println!("{}", name);
+        }
+        let filea = root_inode.find("filea").unwrap();
+        let greet_str = "Hello, world!";
+        filea.write_at(0, greet_str.as_bytes());
+        //let mut buffer = [0u8; 512];
+        let mut buffer = [0u8; 233];
+        let len = filea.read_at(0, &mut buffer);
+        assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap(),);
+
+        let mut random_str_test = |len: usize| {
+            filea.clear();
+            assert_eq!(filea.read_at(0, &mut buffer), 0,);
+            let mut str = String::new();
+            use rand;
+            // random digit
+            for _ in 0..len {
+                str.push(char::from('0' as u8 + rand::random::<u8>() % 10));
+            }
+            filea.write_at(0, str.as_bytes());
+            let mut read_buffer = [0u8; 127];
+            let mut offset = 0usize;
+            let mut read_str = String::new();
+            loop {
+                let len = filea.read_at(offset, &mut read_buffer);
+                if len == 0 {
+                    break;
+                }
+                offset += len;
+                read_str.push_str(core::str::from_utf8(&read_buffer[..len]).unwrap());
+            }
+            assert_eq!(str, read_str);
+        };
+
+        random_str_test(4 * BLOCK_SZ);
+        random_str_test(8 * BLOCK_SZ + BLOCK_SZ / 2);
+        random_str_test(100 * BLOCK_SZ);
+        random_str_test(70 * BLOCK_SZ + BLOCK_SZ / 7);
+        random_str_test((12 + 128) * BLOCK_SZ);
+        random_str_test(400 * BLOCK_SZ);
+        random_str_test(1000 * BLOCK_SZ);
+        random_str_test(2000 * BLOCK_SZ);
+
+        // directories
+        assert_eq!(root_inode.find("..").unwrap().inode_id(), 0);
+        let dira = root_inode.mkdir("dira").unwrap();
+        assert!(dira.is_dir());
+        assert!(root_inode.mkdir("dira").is_none());
+        assert!(root_inode.create("dira").is_none());
+        assert_eq!(dira.ls(), [".", ".."]);
+        assert_eq!(dira.find(".").unwrap().inode_id(), dira.inode_id());
+        assert_eq!(dira.find("..").unwrap().inode_id(), 0);
+        let dirb = dira.mkdir("dirb").unwrap();
+        assert_eq!(dirb.find("..").unwrap().inode_id(), dira.inode_id());
+        let fileb = dirb.create("fileb").unwrap();
+        assert!(!fileb.is_dir());
+        assert!(fileb.find(".").is_none());
+        assert!(fileb.create("filec").is_none());
+        // only empty directories can be removed
+        assert!(!dira.rmdir("dirb"));
+        assert!(!dira.rmdir(".."));
+        assert!(!dirb.rmdir("fileb"));
+        let dirc = dira.mkdir("dirc").unwrap();
+        let dirc_id = dirc.inode_id();
+        drop(dirc);
+        assert!(dira.rmdir("dirc"));
+        assert!(dira.find("dirc").is_none());
+        // the removed dirent slot and inode are reused
+        let dird = dira.mkdir("dird").unwrap();
+        assert_eq!(dira.ls(), [".", "..", "dirb", "dird"]);
+        assert_eq!(dird.inode_id(), dirc_id);
+        assert_eq!(root_inode.nlink(), 3);
+        assert_eq!(dira.nlink(), 4);
+
+        // links
+        assert_eq!(fileb.nlink(), 1);
+        assert!(dira.link("filea", &fileb));
+        assert!(!dira.link("filea", &fileb));
+        assert!(!root_inode.link("dirlink", &dira));
+        assert_eq!(fileb.nlink(), 2);
+        fileb.write_at(0, greet_str.as_bytes());
+        assert!(dirb.unlink("fileb"));
+        assert!(!dirb.unlink("fileb"));
+        assert!(!dira.unlink("dirb"));
+        let filea_link = dira.find("filea").unwrap();
+        assert_eq!(filea_link.nlink(), 1);
+        let len = filea_link.read_at(0, &mut buffer);
+        assert_eq!(greet_str.as_bytes(), &buffer[..len]);
+        let fileb_id = fileb.inode_id();
+        assert!(dira.unlink("filea"));
+        // an open inode outlives its last link
+        assert_eq!(fileb.read_at(0, &mut buffer), greet_str.len());
+        assert_ne!(dirb.create("filex").unwrap().inode_id(), fileb_id);
+        assert!(dirb.unlink("filex"));
+        // and is freed once it is closed
+        drop(filea_link);
+        drop(fileb);
+        assert_eq!(dirb.create("filec").unwrap().inode_id(), fileb_id);
+
+        // rename
+        assert!(dirb.rename("filec", &dirb, "filed"));
+        assert!(dirb.find("filec").is_none());
+        assert_eq!(dirb.find("filed").unwrap().inode_id(), fileb_id);
+        assert!(dira.rename("dirb", &root_inode, "dirb"));
+        assert_eq!(dirb.find("..").unwrap().inode_id(), 0);
+        assert_eq!(dira.nlink(), 3);
+        assert_eq!(root_inode.nlink(), 4);
+        assert!(!root_inode.rename("dira", &dira, "dirx"));
+        assert!(!root_inode.rename("dirb", &dirb, "dirx"));
+        // replace an existing file
+        let filee = root_inode.create("filee").unwrap();
+        assert!(root_inode.rename("filee", &dirb, "filed"));
+        assert_eq!(dirb.find("filed").unwrap().inode_id(), filee.inode_id());
+        assert!(root_inode.find("filee").is_none());
+        assert!(!dirb.rename("filed", &dirb, ".."));
+
+        // metadata
+        efs.lock().set_clock(|| 42);
+        let fileg = dirb.create("fileg").unwrap();
+        fileg.write_at(0, &[0u8; 3 * BLOCK_SZ]);
+        let metadata = fileg.metadata();
+        assert_eq!(metadata.inode_id, fileg.inode_id());
+        assert!(!metadata.is_dir);
+        assert_eq!(metadata.size as usize, 3 * BLOCK_SZ);
+        assert_eq!(metadata.blocks, 3);
+        assert_eq!(metadata.nlink, 1);
+        assert_eq!((metadata.mtime, metadata.ctime), (42, 42));
+        assert!(dirb.metadata().is_dir);
+        assert_eq!(dirb.metadata().mtime, 42);
+
+        // read_dir skips removed dirents and resumes from an offset
+        let entries = dira.read_dir(0);
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, [".", "..", "dird"]);
+        assert!(entries.iter().all(|entry| entry.is_dir));
+        assert_eq!(entries[2].inode_id, dird.inode_id());
+        let rest = dira.read_dir(entries[1].next_offset);
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].name, "dird");
+        assert!(dira.read_dir(rest[0].next_offset).is_empty());
+        assert!(
+            !dirb
+                .read_dir(0)
+                .iter()
+                .find(|e| e.name == "fileg")
+                .unwrap()
+                .is_dir
+        );
+        assert!(fileg.read_dir(0).is_empty());
+        assert_eq!(fsck(block_file, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn long_names() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/names.img", 4096)?;
+        // the fixed 32-byte dirents cap names at 27 bytes
+        EasyFileSystem::create_with_format(block_file.clone(), 4096, 1, DirEntryFormat::Short);
+        let efs = EasyFileSystem::open(block_file.clone());
+        assert_eq!(efs.lock().dirent_format(), DirEntryFormat::Short);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let short_name = "a".repeat(27);
+        assert!(root_inode.create(&short_name).is_some());
+        assert!(root_inode.create("adder_peterson_yield_bench_x").is_none());
+        let efs = EasyFileSystem::open(reopen(&block_file)?);
+        assert!(EasyFileSystem::root_inode(&efs).find(&short_name).is_some());
+
+        EasyFileSystem::create(block_file.clone(), 4096, 1);
+        let efs = EasyFileSystem::open(block_file.clone());
+        assert_eq!(efs.lock().dirent_format(), DirEntryFormat::Long);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let long_name = "b".repeat(255);
+        assert!(root_inode.create(&long_name).is_some());
+        assert!(root_inode.create(&"c".repeat(256)).is_none());
+        assert!(root_inode.create("adder_peterson_yield_bench").is_some());
+        // records are sized to their names, `.` and `..` take 12 bytes each
+        assert_eq!(root_inode.metadata().size, 12 + 12 + 264 + 36);
+        assert!(root_inode.find(&long_name).is_some());
+        assert!(root_inode.unlink(&long_name));
+        assert!(root_inode.unlink("adder_peterson_yield_bench"));
+        root_inode.create("filea");
+        root_inode.create("fileb");
+        // the removed records are reused
+        assert_eq!(root_inode.metadata().size, 12 + 12 + 264 + 36);
+        let efs = EasyFileSystem::open(reopen(&block_file)?);
+        assert_eq!(
+            EasyFileSystem::root_inode(&efs).ls(),
+            [".", "..", "filea", "fileb"]
+        );
+        assert_eq!(fsck(block_file, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_ops() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/concurrent.img", 4096)?;
+        let efs = EasyFileSystem::create(block_file.clone(), 4096, 1);
+        efs.lock().set_park(std::thread::yield_now);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        // readers and writers of different files run at once
+        let dirc = Arc::new(root_inode.mkdir("dirc").unwrap());
+        let pattern = |seed: usize, len: usize| -> Vec<u8> {
+            (0..len).map(|i| (i * 7 + seed) as u8).collect()
+        };
+        let filer = dirc.create("filer").unwrap();
+        filer.write_at(0, &pattern(0, 20 * BLOCK_SZ));
+        let mut threads = Vec::new();
+        for t in 1..=4 {
+            let dirc = dirc.clone();
+            threads.push(std::thread::spawn(move || {
+                let file = dirc.create(&format!("file{}", t)).unwrap();
+                let data = pattern(t, 40 * BLOCK_SZ + t);
+                for (i, chunk) in data.chunks(3 * BLOCK_SZ + t).enumerate() {
+                    file.write_at(i * (3 * BLOCK_SZ + t), chunk);
+                    let temp = dirc.create(&format!("temp{}", t)).unwrap();
+                    temp.write_at(0, chunk);
+                    assert!(dirc.unlink(&format!("temp{}", t)));
+                }
+                let mut read = vec![0u8; data.len()];
+                assert_eq!(file.read_at(0, &mut read), data.len());
+                assert_eq!(read, data);
+            }));
+        }
+        for _ in 0..2 {
+            let filer = filer.clone();
+            threads.push(std::thread::spawn(move || {
+                let mut read = vec![0u8; 20 * BLOCK_SZ];
+                for _ in 0..50 {
+                    assert_eq!(filer.read_at(0, &mut read), read.len());
+                    assert_eq!(read, pattern(0, read.len()));
+                }
+            }));
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let mut names = dirc.ls();
+        names.sort();
+        assert_eq!(
+            names,
+            [".", "..", "file1", "file2", "file3", "file4", "filer"]
+        );
+        // the last temporary file is freed by the next operation
+        root_inode.sync_fs();
+        assert_eq!(fsck(block_file, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn journal_replay() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/journal.img", 4096)?;
+        let device: Arc<dyn BlockDevice> = block_file.clone();
+        let efs = EasyFileSystem::create(block_file.clone(), 4096, 1);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let journal = efs.lock().journal().unwrap();
+        // `open` installs a transaction a crash left committed
+        let before = read_image(&block_file)?;
+        let greet_str = "Hello, world!";
+        let filej = root_inode.create("filej").unwrap();
+        filej.write_at(0, greet_str.as_bytes());
+        let after = read_image(&block_file)?;
+        let changed: Vec<usize> = (0..4096)
+            .filter(|id| {
+                !journal.region().contains(id) && block(&before, *id) != block(&after, *id)
+            })
+            .collect();
+        assert!(changed.len() >= 3);
+        // crash right after the commit record, nothing reached its place
+        {
+            let mut file = block_file.0.lock().unwrap();
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&before)?;
         }
-        filea.write_at(0, str.as_bytes());
-        let mut read_buffer = [0u8; 127];
-        let mut offset = 0usize;
-        let mut read_str = String::new();
-        loop {
-            let len = filea.read_at(offset, &mut read_buffer);
-            if len == 0 {
-                break;
+        let updates: Vec<(usize, Vec<u8>)> =
+            changed.iter().map(|&id| (id, block(&after, id))).collect();
+        let updates: Vec<(usize, &[u8])> =
+            updates.iter().map(|(id, data)| (*id, &data[..])).collect();
+        journal.log(&device, &updates);
+        let crashed = reopen(&block_file)?;
+        let efs = EasyFileSystem::open(crashed.clone());
+        let replayed = read_image(&block_file)?;
+        assert!(changed
+            .iter()
+            .all(|&id| block(&replayed, id) == block(&after, id)));
+        assert_eq!(journal.replay(&device), 0);
+        let mut buffer = [0u8; 32];
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let len = root_inode.find("filej").unwrap().read_at(0, &mut buffer);
+        assert_eq!(greet_str.as_bytes(), &buffer[..len]);
+        assert_eq!(fsck(crashed, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn fsck_detect_repair() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/fsck.img", 4096)?;
+        let device: Arc<dyn BlockDevice> = block_file.clone();
+        let efs = EasyFileSystem::create(block_file.clone(), 4096, 1);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        root_inode
+            .create("filea")
+            .unwrap()
+            .write_at(0, &[1u8; 3 * BLOCK_SZ]);
+        assert_eq!(fsck(device.clone(), false), []);
+        let leaked_inode = efs.lock().alloc_inode().unwrap();
+        let leaked_block = efs.lock().alloc_data().unwrap();
+        efs.lock().commit();
+        let problems = fsck(device.clone(), true);
+        assert_eq!(
+            problems,
+            [
+                FsckProblem::OrphanInode(leaked_inode),
+                FsckProblem::OrphanBlock(leaked_block)
+            ]
+        );
+        assert_eq!(fsck(device.clone(), false), []);
+        // a free block still in use
+        let data_area_start = efs.lock().get_data_block_id(0);
+        let used_block = (data_area_start..)
+            .find(|&block_id| {
+                efs.lock()
+                    .data_bitmap
+                    .is_set(&device, (block_id - data_area_start) as usize)
+            })
+            .unwrap();
+        efs.lock().dealloc_data(used_block);
+        efs.lock().commit();
+        let problems = fsck(device.clone(), true);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0],
+            FsckProblem::BlockNotAllocated { block_id, .. } if block_id == used_block
+        ));
+        assert_eq!(fsck(device.clone(), false), []);
+        // repairs over more blocks than the journal holds, a crash wrote the dirents
+        // of new links but not the link counts
+        let journal = efs.lock().journal().unwrap();
+        let dirl = root_inode.mkdir("dirl").unwrap();
+        let files: Vec<_> = (0..6 * journal.capacity())
+            .map(|i| dirl.create(&format!("f{}", i)).unwrap())
+            .collect();
+        root_inode.sync_fs();
+        let before = read_image(&block_file)?;
+        for (i, file) in files.iter().enumerate() {
+            assert!(dirl.link(&format!("l{}", i), file));
+        }
+        root_inode.sync_fs();
+        let inode_block = |inode_id: u32| efs.lock().get_disk_inode_pos(inode_id).0 as usize;
+        let dirl_block = inode_block(dirl.inode_id());
+        let mut stale: Vec<u32> = files
+            .iter()
+            .map(|file| file.inode_id())
+            .filter(|&inode_id| inode_block(inode_id) != dirl_block)
+            .collect();
+        stale.sort_unstable();
+        {
+            let mut file = block_file.0.lock().unwrap();
+            for &inode_id in stale.iter() {
+                let id = inode_block(inode_id);
+                file.seek(SeekFrom::Start((id * BLOCK_SZ) as u64))?;
+                file.write_all(&block(&before, id))?;
             }
-            offset += len;
-            read_str.push_str(core::str::from_utf8(&read_buffer[..len]).unwrap());
         }
-        assert_eq!(str, read_str);
-    };
+        // a device of its own, the cache of `device` still has the link counts
+        let crashed = reopen(&block_file)?;
+        let problems = fsck(crashed.clone(), true);
+        assert_eq!(problems.len(), stale.len());
+        assert!(problems
+            .iter()
+            .zip(stale.iter())
+            .all(|(problem, &id)| matches!(
+                *problem,
+                FsckProblem::WrongLinkCount { inode_id, nlink: 1, links: 2 } if inode_id == id
+            )));
+        assert_eq!(fsck(crashed, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn fsck_repair_bad_dirent() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/dirent.img", 4096)?;
+        EasyFileSystem::create(block_file.clone(), 4096, 1);
+        let efs = EasyFileSystem::open(block_file.clone());
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        root_inode.create("good").unwrap();
+        let bad_inode = root_inode.create("bad").unwrap().inode_id();
+        // point the dirent `bad` past the inode area, its inode is left unreachable
+        let mut content = vec![0u8; root_inode.metadata().size as usize];
+        root_inode.read_at(0, &mut content);
+        let name_at = content.windows(3).position(|name| name == b"bad").unwrap();
+        root_inode.write_at(name_at - 8, &u32::MAX.to_ne_bytes());
+        root_inode.sync_fs();
+        drop(root_inode);
+        drop(efs);
+        assert_eq!(
+            fsck(block_file.clone(), true),
+            [
+                FsckProblem::BadDirent {
+                    dir: 0,
+                    name: String::from("bad"),
+                    inode_id: u32::MAX,
+                },
+                FsckProblem::OrphanInode(bad_inode),
+            ]
+        );
+        // the repairs are on the image, not only in the cache
+        let reopened = reopen(&block_file)?;
+        assert_eq!(fsck(reopened.clone(), false), []);
+        let efs = EasyFileSystem::open(reopened);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        assert_eq!(root_inode.ls(), [".", "..", "good"]);
+        assert!(root_inode.create("bad").is_some());
+        Ok(())
+    }
 
-    random_str_test(4 * BLOCK_SZ);
-    random_str_test(8 * BLOCK_SZ + BLOCK_SZ / 2);
-    random_str_test(100 * BLOCK_SZ);
-    random_str_test(70 * BLOCK_SZ + BLOCK_SZ / 7);
-    random_str_test((12 + 128) * BLOCK_SZ);
-    random_str_test(400 * BLOCK_SZ);
-    random_str_test(1000 * BLOCK_SZ);
-    random_str_test(2000 * BLOCK_SZ);
+    #[test]
+    fn block_cache() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/cache.img", 4096)?;
+        let device: Arc<dyn BlockDevice> = block_file.clone();
+        let efs = EasyFileSystem::create(block_file.clone(), 4096, 1);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let greet_str = "Hello, world!";
+        root_inode
+            .create("filej")
+            .unwrap()
+            .write_at(0, greet_str.as_bytes());
+        let before = block_cache_stats();
+        read_all(&root_inode.find("filej").unwrap());
+        read_all(&root_inode.find("filej").unwrap());
+        let after = block_cache_stats();
+        assert!(after.hits > before.hits);
+        // more blocks in use than cached, the clock evicts
+        block_cache_set_capacity(2);
+        let filek = root_inode.create("filek").unwrap();
+        filek.write_at(0, &[7u8; 20 * BLOCK_SZ]);
+        assert_eq!(read_all(&filek), [7u8; 20 * BLOCK_SZ]);
+        assert!(block_cache_stats().evictions > after.evictions);
+        block_cache_set_capacity(256);
+        // nothing reaches the disk before sync with write-back
+        block_cache_set_policy(WritePolicy::WriteBack);
+        let before = read_image(&block_file)?;
+        root_inode
+            .create("filel")
+            .unwrap()
+            .write_at(0, greet_str.as_bytes());
+        assert!(read_image(&block_file)? == before);
+        efs.lock().sync();
+        assert!(read_image(&block_file)? != before);
+        block_cache_set_policy(WritePolicy::WriteThrough);
+        let mut buffer = [0u8; 32];
+        let reopened = EasyFileSystem::open(reopen(&block_file)?);
+        let len = EasyFileSystem::root_inode(&reopened)
+            .find("filel")
+            .unwrap()
+            .read_at(0, &mut buffer);
+        assert_eq!(greet_str.as_bytes(), &buffer[..len]);
+        assert_eq!(fsck(device.clone(), false), []);
 
-    Ok(())
+        // a second device shares the cache without mixing up blocks of the same ID
+        let other_file = test_image("target/cache2.img", 4096)?;
+        let other_device: Arc<dyn BlockDevice> = other_file.clone();
+        assert!(!EasyFileSystem::probe(&other_device));
+        let other_efs = EasyFileSystem::create(other_file, 4096, 1);
+        assert!(EasyFileSystem::probe(&other_device));
+        let other_root = EasyFileSystem::root_inode(&other_efs);
+        other_root
+            .create("filel")
+            .unwrap()
+            .write_at(0, &[9u8; 3 * BLOCK_SZ]);
+        assert_eq!(
+            read_all(&other_root.find("filel").unwrap()),
+            [9u8; 3 * BLOCK_SZ]
+        );
+        let len = root_inode.find("filel").unwrap().read_at(0, &mut buffer);
+        assert_eq!(greet_str.as_bytes(), &buffer[..len]);
+        assert!(other_root.find("filej").is_none());
+        assert_eq!(fsck(other_device, false), []);
+        assert_eq!(fsck(device, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn full_image() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/full.img", 4096)?;
+        let device: Arc<dyn BlockDevice> = block_file.clone();
+        let efs = EasyFileSystem::create(block_file, 4096, 1);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        // writes are short and nothing is created
+        let filem = root_inode.create("filem").unwrap();
+        let data = vec![5u8; 4096 * BLOCK_SZ];
+        let written = filem.write_at(0, &data);
+        assert!(written > 0 && written < data.len());
+        assert_eq!(filem.metadata().size as usize, written);
+        assert_eq!(filem.write_at(written, &data[..BLOCK_SZ]), 0);
+        let (inodes, _) = efs.lock().usage();
+        assert!(root_inode.mkdir("dirn").is_none());
+        assert_eq!(efs.lock().usage().0, inodes);
+        // files need no block until the dirents of the root fill their last one
+        let created = (0..)
+            .take_while(|i| root_inode.create(&format!("filen{}", i)).is_some())
+            .count();
+        assert!(!root_inode.link("filel2", &filem));
+        assert_eq!(efs.lock().usage().0, inodes + created);
+        assert_eq!(fsck(device.clone(), false), []);
+        drop(filem);
+        assert!(root_inode.unlink("filem"));
+        assert!(root_inode.mkdir("dirn").is_some());
+        assert_eq!(fsck(device, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn fuse_mount() -> std::io::Result<()> {
+        let _cache = lock_cache();
+        let block_file = test_image("target/mount.img", 8192)?;
+        let device: Arc<dyn BlockDevice> = block_file.clone();
+        let efs = EasyFileSystem::create(block_file, 8192, 1);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        // the host sees the image through FUSE, where mounting is allowed
+        let mountpoint = std::path::Path::new("target/mnt");
+        std::fs::create_dir_all(mountpoint)?;
+        let session = match fuser::spawn_mount2(EasyFuse::new(efs.clone()), mountpoint, &[]) {
+            Ok(session) => session,
+            Err(err) => {
+                println!("FUSE is not available, skipping the mount test: {}", err);
+                return Ok(());
+            }
+        };
+        let data: Vec<u8> = (0..300 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+        std::fs::write(mountpoint.join("big"), &data)?;
+        assert_eq!(std::fs::read(mountpoint.join("big"))?, data);
+        assert_eq!(read_all(&root_inode.find("big").unwrap()), data);
+        std::fs::create_dir(mountpoint.join("dirm"))?;
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let dir = mountpoint.join("dirm");
+                std::thread::spawn(move || {
+                    for j in 0..50 {
+                        std::fs::write(dir.join(format!("{}-{}", i, j)), format!("{}", j)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(std::fs::read_dir(mountpoint.join("dirm"))?.count(), 200);
+        assert_eq!(std::fs::read_to_string(mountpoint.join("dirm/3-42"))?, "42");
+        std::fs::rename(mountpoint.join("big"), mountpoint.join("dirm/big"))?;
+        std::fs::hard_link(mountpoint.join("dirm/big"), mountpoint.join("bigl"))?;
+        assert_eq!(std::fs::metadata(mountpoint.join("bigl"))?.nlink(), 2);
+        OpenOptions::new()
+            .write(true)
+            .open(mountpoint.join("bigl"))?
+            .set_len(100)?;
+        assert_eq!(std::fs::read(mountpoint.join("dirm/big"))?, &data[..100]);
+        assert_eq!(
+            std::fs::remove_dir(mountpoint.join("dirm"))
+                .unwrap_err()
+                .raw_os_error(),
+            Some(libc::ENOTEMPTY)
+        );
+        std::fs::remove_dir_all(mountpoint.join("dirm"))?;
+        std::fs::remove_file(mountpoint.join("bigl"))?;
+        session.join();
+        assert!(root_inode.find("dirm").is_none());
+        assert_eq!(fsck(device, false), []);
+        Ok(())
+    }
 }
@@ -0,0 +1,452 @@
+use easy_fs::{EasyFileSystem, Inode, Metadata};
+use fuser::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyStatfs, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
+};
+use libc::{c_int, EEXIST, EINVAL, EISDIR, ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY};
+use spin::Mutex;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Attributes only change through this process, the kernel may cache them for a while.
+const TTL: Duration = Duration::from_secs(1);
+
+/// An easy-fs image served to the host kernel through FUSE.
+///
+/// FUSE numbers the root 1, so the inode `n` of easy-fs is `n + 1` here.
+/// Every operation is a transaction of easy-fs, so the image on disk stays
+/// consistent whatever the write policy, `fsync` and unmounting write back all.
+pub struct EasyFuse {
+    efs: Arc<Mutex<EasyFileSystem>>,
+    /// Inodes the kernel has looked up with its lookup count, the root is always here.
+    /// An unlinked inode is freed once the kernel forgets it.
+    inodes: HashMap<u64, (Arc<Inode>, u64)>,
+    uid: u32,
+    gid: u32,
+}
+
+fn time(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
+/// Milliseconds since the epoch, the clock of a mounted image.
+fn host_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+impl EasyFuse {
+    pub fn new(efs: Arc<Mutex<EasyFileSystem>>) -> Self {
+        efs.lock().set_clock(host_clock);
+        efs.lock().set_park(std::thread::yield_now);
+        let mut inodes = HashMap::new();
+        inodes.insert(
+            FUSE_ROOT_ID,
+            (Arc::new(EasyFileSystem::root_inode(&efs)), 1),
+        );
+        Self {
+            efs,
+            inodes,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    fn inode(&self, ino: u64) -> Result<Arc<Inode>, c_int> {
+        self.inodes
+            .get(&ino)
+            .map(|(inode, _)| inode.clone())
+            .ok_or(ENOENT)
+    }
+
+    fn dir(&self, ino: u64) -> Result<Arc<Inode>, c_int> {
+        let inode = self.inode(ino)?;
+        if inode.is_dir() {
+            Ok(inode)
+        } else {
+            Err(ENOTDIR)
+        }
+    }
+
+    /// Remember `inode` for the requests naming it later, until the kernel forgets it.
+    fn remember(&mut self, inode: Arc<Inode>) -> FileAttr {
+        let attr = self.attr(&inode.metadata());
+        self.inodes.entry(attr.ino).or_insert((inode, 0)).1 += 1;
+        attr
+    }
+
+    fn attr(&self, metadata: &Metadata) -> FileAttr {
+        FileAttr {
+            ino: metadata.inode_id as u64 + 1,
+            size: metadata.size as u64,
+            blocks: metadata.blocks as u64,
+            atime: time(metadata.mtime),
+            mtime: time(metadata.mtime),
+            ctime: time(metadata.ctime),
+            crtime: time(metadata.ctime),
+            kind: if metadata.is_dir {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            },
+            perm: metadata.mode,
+            nlink: metadata.nlink as u32,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    /// Names easy-fs can't store, easy-fs itself only says no.
+    fn check_name<'a>(&self, name: &'a OsStr) -> Result<&'a str, c_int> {
+        let name = name.to_str().ok_or(EINVAL)?;
+        if name.len() > self.efs.lock().dirent_format().name_limit() {
+            Err(ENAMETOOLONG)
+        } else {
+            Ok(name)
+        }
+    }
+
+    fn create_inode(&mut self, parent: u64, name: &OsStr, is_dir: bool) -> Result<FileAttr, c_int> {
+        let parent = self.dir(parent)?;
+        let name = self.check_name(name)?;
+        if parent.find(name).is_some() {
+            return Err(EEXIST);
+        }
+        let inode = if is_dir {
+            parent.mkdir(name)
+        } else {
+            parent.create(name)
+        };
+        Ok(self.remember(inode.ok_or(ENOSPC)?))
+    }
+
+    fn remove(&mut self, parent: u64, name: &OsStr, is_dir: bool) -> Result<(), c_int> {
+        let parent = self.dir(parent)?;
+        let name = name.to_str().ok_or(ENOENT)?;
+        let inode = parent.find(name).ok_or(ENOENT)?;
+        match (is_dir, inode.is_dir()) {
+            (true, true) if parent.rmdir(name) => Ok(()),
+            (true, true) => Err(ENOTEMPTY),
+            (true, false) => Err(ENOTDIR),
+            (false, true) => Err(EISDIR),
+            (false, false) if parent.unlink(name) => Ok(()),
+            (false, false) => Err(ENOENT),
+        }
+    }
+
+    /// easy-fs can only drop the whole content, a shorter size keeps the head of it.
+    /// Growing stops where the image is full.
+    fn truncate(inode: &Inode, size: u64) -> Result<(), c_int> {
+        let old_size = inode.metadata().size as u64;
+        match size.cmp(&old_size) {
+            Ordering::Greater => {
+                let zeros = vec![0u8; (size - old_size) as usize];
+                if inode.write_at(old_size as usize, &zeros) < zeros.len() {
+                    return Err(ENOSPC);
+                }
+            }
+            Ordering::Less => {
+                let mut head = vec![0u8; size as usize];
+                inode.read_at(0, &mut head);
+                inode.clear();
+                inode.write_at(0, &head);
+            }
+            Ordering::Equal => {}
+        }
+        Ok(())
+    }
+}
+
+impl Filesystem for EasyFuse {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let found = self
+            .dir(parent)
+            .and_then(|dir| dir.find(name.to_str().ok_or(ENOENT)?).ok_or(ENOENT));
+        match found {
+            Ok(inode) => reply.entry(&TTL, &self.remember(inode), 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.inode(ino) {
+            Ok(inode) => reply.attr(&TTL, &self.attr(&inode.metadata())),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    /// Only the size can be changed, the rest is accepted and ignored.
+    fn setattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        let inode = match self.inode(ino) {
+            Ok(inode) => inode,
+            Err(err) => return reply.error(err),
+        };
+        if let Some(size) = size {
+            if inode.is_dir() {
+                return reply.error(EISDIR);
+            }
+            if let Err(err) = Self::truncate(&inode, size) {
+                return reply.error(err);
+            }
+        }
+        reply.attr(&TTL, &self.attr(&inode.metadata()));
+    }
+
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        match self.create_inode(parent, name, true) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove(parent, name, false) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        match self.remove(parent, name, true) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        // RENAME_NOREPLACE and RENAME_EXCHANGE are not supported
+        if flags != 0 {
+            return reply.error(EINVAL);
+        }
+        let result = (|| {
+            let parent = self.dir(parent)?;
+            let new_parent = self.dir(newparent)?;
+            let name = name.to_str().ok_or(ENOENT)?;
+            let new_name = self.check_name(newname)?;
+            let inode = parent.find(name).ok_or(ENOENT)?;
+            match new_parent.find(new_name) {
+                Some(old) if old.is_dir() && !inode.is_dir() => return Err(EISDIR),
+                Some(old) if !old.is_dir() && inode.is_dir() => return Err(ENOTDIR),
+                Some(old) if old.is_dir() => return Err(ENOTEMPTY),
+                _ => {}
+            }
+            if parent.rename(name, &new_parent, new_name) {
+                Ok(())
+            } else {
+                Err(EINVAL)
+            }
+        })();
+        match result {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn link(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        let result = (|| {
+            let inode = self.inode(ino)?;
+            let new_parent = self.dir(newparent)?;
+            let new_name = self.check_name(newname)?;
+            if new_parent.find(new_name).is_some() {
+                return Err(EEXIST);
+            }
+            if inode.is_dir() {
+                return Err(libc::EPERM);
+            }
+            if !new_parent.link(new_name, &inode) {
+                return Err(ENOSPC);
+            }
+            Ok(self.remember(inode))
+        })();
+        match result {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.inode(ino) {
+            Ok(inode) => {
+                let mut buf = vec![0u8; size as usize];
+                let len = inode.read_at(offset as usize, &mut buf);
+                reply.data(&buf[..len]);
+            }
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        match self.inode(ino) {
+            // a short write tells the kernel where the image ran full
+            Ok(inode) => match inode.write_at(offset as usize, data) {
+                0 if !data.is_empty() => reply.error(ENOSPC),
+                written => reply.written(written as u32),
+            },
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn forget(&mut self, _req: &Request<'_>, ino: u64, nlookup: u64) {
+        if let Some((_, count)) = self.inodes.get_mut(&ino) {
+            *count -= nlookup.min(*count);
+            if *count == 0 && ino != FUSE_ROOT_ID {
+                self.inodes.remove(&ino);
+            }
+        }
+    }
+
+    /// Unlinked inodes the kernel still knew are freed before writing back.
+    fn destroy(&mut self) {
+        self.inodes.retain(|&ino, _| ino == FUSE_ROOT_ID);
+        self.inodes[&FUSE_ROOT_ID].0.sync_fs();
+    }
+
+    /// The cache is shared by all files, so everything is written back.
+    fn fsync(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.inodes[&FUSE_ROOT_ID].0.sync_fs();
+        reply.ok();
+    }
+
+    /// The offsets are the ones of `Inode::read_dir`, so a listing resumes where it stopped.
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let dir = match self.dir(ino) {
+            Ok(dir) => dir,
+            Err(err) => return reply.error(err),
+        };
+        for entry in dir.read_dir(offset as usize) {
+            let kind = if entry.is_dir {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            if reply.add(
+                entry.inode_id as u64 + 1,
+                entry.next_offset as i64,
+                kind,
+                &entry.name,
+            ) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        let efs = self.efs.lock();
+        let super_block = efs.super_block();
+        let (inodes, data_blocks) = efs.usage();
+        let total_inodes = efs.inode_bitmap.maximum() as u64;
+        let total_blocks = super_block.data_area_blocks as u64;
+        let free_blocks = total_blocks - data_blocks as u64;
+        reply.statfs(
+            total_blocks,
+            free_blocks,
+            free_blocks,
+            total_inodes,
+            total_inodes - inodes as u64,
+            512,
+            efs.dirent_format().name_limit() as u32,
+            512,
+        );
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        match self.create_inode(parent, name, false) {
+            Ok(attr) => reply.created(&TTL, &attr, 0, 0, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+}
@@ -27,27 +27,25 @@ impl Bitmap {
 
     pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
         for block_id in 0..self.blocks {
-            let pos = get_block_cache(
+            let block_cache = get_block_cache(
                 block_id + self.start_block_id as usize,
                 Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |bitmap_block: &mut BitmapBlock| {
-                if let Some((bits64_pos, inner_pos)) = bitmap_block
+            );
+            let mut block_cache = block_cache.lock();
+            // only the block we allocate from joins the transaction
+            let found = block_cache.read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block
                     .iter()
                     .enumerate()
                     .find(|(_, bits64)| **bits64 != u64::MAX)
                     .map(|(bits64_pos, bits64)| (bits64_pos, bits64.trailing_ones() as usize))
-                {
-                    // modify cache
-                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
-                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos as usize)
-                } else {
-                    None
-                }
             });
-            if pos.is_some() {
-                return pos;
+            if let Some((bits64_pos, inner_pos)) = found {
+                // modify cache
+                block_cache.modify(0, |bitmap_block: &mut BitmapBlock| {
+                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+                });
+                return Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos);
             }
         }
         None
@@ -63,6 +61,41 @@ impl Bitmap {
             });
     }
 
+    pub fn is_set(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0
+            })
+    }
+
+    /// Mark `bit` allocated.
+    pub fn set(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .modify(0, |bitmap_block: &mut BitmapBlock| {
+                bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+            });
+    }
+
+    /// Return the number of allocated bits.
+    pub fn count(&self, block_device: &Arc<dyn BlockDevice>) -> usize {
+        (0..self.blocks)
+            .map(|block_id| {
+                get_block_cache(block_id + self.start_block_id, Arc::clone(block_device))
+                    .lock()
+                    .read(0, |bitmap_block: &BitmapBlock| {
+                        bitmap_block
+                            .iter()
+                            .map(|bits64| bits64.count_ones() as usize)
+                            .sum::<usize>()
+                    })
+            })
+            .sum()
+    }
+
     pub fn maximum(&self) -> usize {
         self.blocks * BLOCK_BITS
     }
@@ -3,10 +3,11 @@ use core::mem::ManuallyDrop;
 use core::ptr::{addr_of, addr_of_mut};
 use core::slice;
 
-use super::{BlockDevice, BLOCK_SZ};
+use super::{BlockDevice, Journal, BLOCK_SZ};
 use alloc::boxed::Box;
-use alloc::collections::VecDeque;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 use spin::Mutex;
 
@@ -53,6 +54,8 @@ pub struct BlockCache {
     block_id: usize,
     block_device: Arc<dyn BlockDevice>,
     modified: bool,
+    /// The changes are metadata and must be written through the journal.
+    journaled: bool,
 }
 
 impl BlockCache {
@@ -66,6 +69,7 @@ impl BlockCache {
             block_id,
             block_device,
             modified: false,
+            journaled: false,
         }
     }
 
@@ -102,11 +106,25 @@ impl BlockCache {
         f(self.get_ref(offset))
     }
 
+    /// Modify metadata, the block stays in the cache until it is committed.
     pub fn modify<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        self.journaled = true;
         f(self.get_mut(offset))
     }
 
+    /// Modify file content, which bypasses the journal and may be written back at any time.
+    pub fn modify_data<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        f(self.get_mut(offset))
+    }
+
+    /// Uncommitted metadata can't be written back on eviction,
+    /// with write-back no dirty block can.
+    fn is_pinned(&self, policy: WritePolicy) -> bool {
+        self.modified && (self.journaled || policy == WritePolicy::WriteBack)
+    }
+
     pub fn sync(&mut self) {
+        self.journaled = false;
         if self.modified {
             self.modified = false;
             self.block_device
@@ -123,14 +141,59 @@ impl Drop for BlockCache {
 
 const BLOCK_CACHE_SIZE: usize = 16;
 
+/// Metadata blocks a single operation may dirty, kept free in the journal
+/// while commits are delayed.
+pub const OPERATION_BLOCKS: usize = 16;
+
+/// When the changes of an operation reach the disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Every operation is committed when it completes.
+    WriteThrough,
+    /// Dirty blocks stay in the cache until `EasyFileSystem::sync`, or until
+    /// they fill half the cache or the journal, then they are committed as one.
+    WriteBack,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockCacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+struct CacheSlot {
+    /// The `device_key` of the device the block is on.
+    device: usize,
+    block_id: usize,
+    cache: Arc<Mutex<BlockCache>>,
+    /// Set on every access, cleared when the clock hand passes.
+    referenced: bool,
+}
+
+/// Block caches replaced with the clock algorithm.
+///
+/// Blocks in use or pinned are skipped by the hand, if all of them are
+/// the cache grows over its capacity and shrinks back on later misses.
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    slots: Vec<CacheSlot>,
+    /// Device key and block ID to its slot.
+    index: BTreeMap<(usize, usize), usize>,
+    hand: usize,
+    capacity: usize,
+    policy: WritePolicy,
+    stats: BlockCacheStats,
 }
 
 impl BlockCacheManager {
     pub fn new() -> Self {
         Self {
-            queue: VecDeque::new(),
+            slots: Vec::new(),
+            index: BTreeMap::new(),
+            hand: 0,
+            capacity: BLOCK_CACHE_SIZE,
+            policy: WritePolicy::WriteThrough,
+            stats: BlockCacheStats::default(),
         }
     }
 
@@ -139,34 +202,122 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
+        let device = device_key(&block_device);
+        if let Some(&slot) = self.index.get(&(device, block_id)) {
+            self.stats.hits += 1;
+            self.slots[slot].referenced = true;
+            return Arc::clone(&self.slots[slot].cache);
+        }
+        self.stats.misses += 1;
+        while self.slots.len() > self.capacity {
+            match self.victim() {
+                Some(slot) => self.remove(slot),
+                None => break,
+            }
+        }
+        // load block into mem
+        let block_cache = Arc::new(Mutex::new(BlockCache::new(
+            block_id,
+            Arc::clone(&block_device),
+        )));
+        let slot = CacheSlot {
+            device,
+            block_id,
+            cache: Arc::clone(&block_cache),
+            referenced: true,
+        };
+        let victim = if self.slots.len() == self.capacity {
+            self.victim()
         } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
+            None
+        };
+        match victim {
+            Some(victim) => {
+                self.stats.evictions += 1;
+                let old = &self.slots[victim];
+                self.index.remove(&(old.device, old.block_id));
+                self.index.insert((device, block_id), victim);
+                // the old block is written back when dropped here
+                self.slots[victim] = slot;
+            }
+            None => {
+                self.index.insert((device, block_id), self.slots.len());
+                self.slots.push(slot);
+            }
+        }
+        block_cache
+    }
+
+    /// Move the hand to a slot which can be replaced, giving every
+    /// referenced slot a second chance.
+    fn victim(&mut self) -> Option<usize> {
+        for _ in 0..2 * self.slots.len() {
+            let slot = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            let entry = &mut self.slots[slot];
+            if Arc::strong_count(&entry.cache) > 1 || entry.cache.lock().is_pinned(self.policy) {
+                continue;
+            }
+            if entry.referenced {
+                entry.referenced = false;
+            } else {
+                return Some(slot);
+            }
+        }
+        None
+    }
+
+    fn remove(&mut self, slot: usize) {
+        self.stats.evictions += 1;
+        let removed = self.slots.swap_remove(slot);
+        self.index.remove(&(removed.device, removed.block_id));
+        if let Some(moved) = self.slots.get(slot) {
+            self.index.insert((moved.device, moved.block_id), slot);
+        }
+        if self.hand >= self.slots.len() {
+            self.hand = 0;
+        }
+    }
+
+    /// Return true if delaying the commit any longer could overflow the cache
+    /// or the `journal` of `device`, with `reserve` blocks left for operations still running.
+    fn must_commit(&self, journal: Option<&Journal>, reserve: usize, device: usize) -> bool {
+        if self.policy == WritePolicy::WriteThrough {
+            return true;
+        }
+        let (mut dirty, mut metadata) = (0, 0);
+        for slot in self.slots.iter() {
+            // blocks in use are being changed, they are counted next time
+            if slot.device != device || Arc::strong_count(&slot.cache) > 1 {
+                continue;
+            }
+            let cache = slot.cache.lock();
+            if cache.modified {
+                dirty += 1;
+                if cache.journaled {
+                    metadata += 1;
                 }
             }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
-                block_id,
-                Arc::clone(&block_device),
-            )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            block_cache
         }
+        dirty * 2 >= self.capacity
+            || journal.map_or(false, |journal| metadata + reserve > journal.capacity())
+    }
+
+    /// All the blocks cached from `device`, to be locked after the manager is released.
+    fn cached_blocks(&self, device: usize) -> Vec<Arc<Mutex<BlockCache>>> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.device == device)
+            .map(|slot| Arc::clone(&slot.cache))
+            .collect()
     }
 }
 
+/// Tells the block devices sharing the cache apart.
+fn device_key(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
 lazy_static! {
     pub static ref BLOCK_CACHE_MANAGER: Mutex<BlockCacheManager> =
         Mutex::new(BlockCacheManager::new());
@@ -181,9 +332,61 @@ pub fn get_block_cache(
         .get_block_cache(block_id, block_device)
 }
 
-pub fn block_cache_sync_all() {
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
+/// Set the number of blocks cached, more blocks are only cached while all are in use.
+pub fn block_cache_set_capacity(capacity: usize) {
+    assert!(capacity > 0);
+    BLOCK_CACHE_MANAGER.lock().capacity = capacity;
+}
+
+/// Switching to `WriteThrough` takes effect with the next commit.
+pub fn block_cache_set_policy(policy: WritePolicy) {
+    BLOCK_CACHE_MANAGER.lock().policy = policy;
+}
+
+pub fn block_cache_stats() -> BlockCacheStats {
+    BLOCK_CACHE_MANAGER.lock().stats
+}
+
+/// Return true if the write policy asks for a commit at the end of an operation.
+pub fn block_cache_must_commit(
+    journal: Option<&Journal>,
+    reserve: usize,
+    block_device: &Arc<dyn BlockDevice>,
+) -> bool {
+    BLOCK_CACHE_MANAGER
+        .lock()
+        .must_commit(journal, reserve, device_key(block_device))
+}
+
+/// Write back all the dirty blocks of `block_device`. With a `journal`, the metadata blocks
+/// are logged first as one transaction, so after a crash either all or none of them are written.
+pub fn block_cache_commit(journal: Option<&Journal>, block_device: &Arc<dyn BlockDevice>) {
+    // the manager is not held here, a block may be locked while the manager is taken
+    let caches = BLOCK_CACHE_MANAGER
+        .lock()
+        .cached_blocks(device_key(block_device));
+    let mut pinned = Vec::new();
+    for cache in caches.iter() {
+        let mut cache = cache.lock();
+        if cache.modified && cache.journaled {
+            pinned.push(cache);
+        } else {
+            // file content goes before the metadata pointing to it
+            cache.sync();
+        }
+    }
+    let journal = journal.filter(|_| !pinned.is_empty());
+    if let Some(journal) = journal {
+        let updates: Vec<(usize, &[u8])> = pinned
+            .iter()
+            .map(|cache| (cache.block_id, cache.cache.as_ref()))
+            .collect();
+        journal.log(block_device, &updates);
+    }
+    for cache in pinned.iter_mut() {
+        cache.sync();
+    }
+    if let Some(journal) = journal {
+        journal.clear(block_device);
     }
 }
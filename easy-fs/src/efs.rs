@@ -1,6 +1,7 @@
 use super::{
-    block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode,
-    SuperBlock,
+    block_cache_commit, block_cache_must_commit, get_block_cache, Bitmap, BlockDevice,
+    DirEntryFormat, DiskInode, DiskInodeType, FsLocks, Inode, Journal, SuperBlock, FEATURE_JOURNAL,
+    FEATURE_LONG_NAMES, OPERATION_BLOCKS,
 };
 use crate::BLOCK_SZ;
 use alloc::sync::Arc;
@@ -12,15 +13,49 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     inode_area_start_block: u32,
     data_area_start_block: u32,
+    /// The data bitmap has bits past the data area, they are never allocated.
+    data_area_blocks: u32,
+    dirent_format: DirEntryFormat,
+    /// Images made without `FEATURE_JOURNAL` have none.
+    journal: Option<Journal>,
+    /// Shared with the inodes, which take this mutex only to allocate.
+    locks: Arc<FsLocks>,
 }
 
 type DataBlock = [u8; BLOCK_SZ];
 
+/// Blocks reserved at the end of a new image for the journal.
+const JOURNAL_BLOCKS: u32 = 64;
+
 impl EasyFileSystem {
+    /// Create an image with long file names and a journal.
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
+    ) -> Arc<Mutex<Self>> {
+        Self::create_with_format(
+            block_device,
+            total_blocks,
+            inode_bitmap_blocks,
+            DirEntryFormat::Long,
+        )
+    }
+
+    /// The smallest image with `inode_bitmap_blocks` that still holds the root directory.
+    pub fn min_blocks(inode_bitmap_blocks: u32) -> u32 {
+        let inode_num = inode_bitmap_blocks as usize * BLOCK_SZ * 8;
+        let inode_area_blocks =
+            ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
+        // a data bitmap block and two data blocks for the dirents of the root
+        1 + inode_bitmap_blocks + inode_area_blocks + JOURNAL_BLOCKS + 3
+    }
+
+    pub fn create_with_format(
+        block_device: Arc<dyn BlockDevice>,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+        dirent_format: DirEntryFormat,
     ) -> Arc<Mutex<Self>> {
         // calculate block size of areas & create bitmaps
         let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
@@ -28,29 +63,35 @@ impl EasyFileSystem {
         let inode_area_blocks =
             ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
+        let data_total_blocks = total_blocks - 1 - inode_total_blocks - JOURNAL_BLOCKS;
         let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
         let data_bitmap = Bitmap::new(
             (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
             data_bitmap_blocks as usize,
         );
+        let journal = Journal::new(total_blocks - JOURNAL_BLOCKS, JOURNAL_BLOCKS);
         let mut efs = Self {
             block_device: Arc::clone(&block_device),
             inode_bitmap,
             data_bitmap,
             inode_area_start_block: 1 + inode_bitmap_blocks,
             data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            data_area_blocks,
+            dirent_format,
+            journal: Some(journal),
+            locks: Arc::new(FsLocks::new(Some(journal), Arc::clone(&block_device))),
         };
-        // clear all blocks
+        // clear all blocks, written at once so they don't fill a write-back cache
         for i in 0..total_blocks {
-            get_block_cache(i as usize, Arc::clone(&block_device))
-                .lock()
-                .modify(0, |data_block: &mut DataBlock| {
-                    for byte in data_block.iter_mut() {
-                        *byte = 0;
-                    }
-                });
+            let block_cache = get_block_cache(i as usize, Arc::clone(&block_device));
+            let mut block_cache = block_cache.lock();
+            block_cache.modify_data(0, |data_block: &mut DataBlock| {
+                for byte in data_block.iter_mut() {
+                    *byte = 0;
+                }
+            });
+            block_cache.sync();
         }
         // initialize SuperBlock
         get_block_cache(0, Arc::clone(&block_device)).lock().modify(
@@ -62,31 +103,55 @@ impl EasyFileSystem {
                     inode_area_blocks,
                     data_bitmap_blocks,
                     data_area_blocks,
+                    match dirent_format {
+                        DirEntryFormat::Short => FEATURE_JOURNAL,
+                        DirEntryFormat::Long => FEATURE_JOURNAL | FEATURE_LONG_NAMES,
+                    },
+                    JOURNAL_BLOCKS,
                 );
             },
         );
         // write back immediately
         // create a inode for root node "/"
-        assert_eq!(efs.alloc_inode(), 0);
+        assert_eq!(efs.alloc_inode(), Some(0));
         let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
         get_block_cache(root_inode_block_id as usize, Arc::clone(&block_device))
             .lock()
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-                disk_inode.initialize(DiskInodeType::Directory);
+                disk_inode.initialize(DiskInodeType::Directory, efs.now());
             });
-        block_cache_sync_all();
-        Arc::new(Mutex::new(efs))
+        let efs = Arc::new(Mutex::new(efs));
+        // ".." of the root is itself
+        let root_inode = Self::root_inode(&efs);
+        assert!(
+            root_inode.init_dir(&root_inode),
+            "No room for the root directory!"
+        );
+        efs.lock().sync();
+        efs
     }
 
+    /// Return true if `block_device` holds an image `open` accepts.
+    pub fn probe(block_device: &Arc<dyn BlockDevice>) -> bool {
+        get_block_cache(0, Arc::clone(block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| super_block.is_valid())
+    }
+
+    /// Open an image, a transaction interrupted by a crash is completed first.
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
         // read SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
-            .lock()
-            .read(0, |super_block: &SuperBlock| {
+        let efs = get_block_cache(0, Arc::clone(&block_device)).lock().read(
+            0,
+            |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
-                let efs = Self {
+                Self {
+                    locks: Arc::new(FsLocks::new(
+                        super_block.journal(),
+                        Arc::clone(&block_device),
+                    )),
                     block_device,
                     inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
                     data_bitmap: Bitmap::new(
@@ -95,23 +160,96 @@ impl EasyFileSystem {
                     ),
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
-                };
-                Arc::new(Mutex::new(efs))
-            })
+                    data_area_blocks: super_block.data_area_blocks,
+                    dirent_format: super_block.dirent_format(),
+                    journal: super_block.journal(),
+                }
+            },
+        );
+        if let Some(journal) = efs.journal {
+            journal.replay(&efs.block_device);
+        }
+        Arc::new(Mutex::new(efs))
     }
 
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
-        let block_device = Arc::clone(&efs.lock().block_device);
         // acquire efs lock temporarily
-        let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
+        let fs = efs.lock();
+        Inode::new(
+            0,
+            fs.dirent_format,
+            fs.inode_area_start_block,
+            Arc::clone(efs),
+            Arc::clone(&fs.locks),
+            Arc::clone(&fs.block_device),
+        )
         // release efs lock
-        Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
+    }
+
+    pub fn super_block(&self) -> SuperBlock {
+        get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| *super_block)
+    }
+
+    /// Return the number of allocated inodes and data blocks.
+    pub fn usage(&self) -> (usize, usize) {
+        (
+            self.inode_bitmap.count(&self.block_device),
+            self.data_bitmap.count(&self.block_device),
+        )
+    }
+
+    pub fn dirent_format(&self) -> DirEntryFormat {
+        self.dirent_format
+    }
+
+    pub fn journal(&self) -> Option<Journal> {
+        self.journal
+    }
+
+    /// End a change made with this mutex held rather than through an `Inode`,
+    /// it is written back as the write policy of the block cache says,
+    /// the metadata always as one atomic transaction.
+    pub fn commit(&self) {
+        if block_cache_must_commit(self.journal.as_ref(), OPERATION_BLOCKS, &self.block_device) {
+            block_cache_commit(self.journal.as_ref(), &self.block_device);
+        }
+    }
+
+    /// Write back everything changed so far, no `Inode` operation may be running.
+    /// `Inode::sync_fs` waits for them instead.
+    pub fn sync(&self) {
+        block_cache_commit(self.journal.as_ref(), &self.block_device);
+    }
+
+    pub(crate) fn locks(&self) -> Arc<FsLocks> {
+        Arc::clone(&self.locks)
+    }
+
+    /// Timestamps are 0 until a clock is set.
+    pub fn set_clock(&mut self, clock: fn() -> u64) {
+        self.locks.set_clock(clock);
+    }
+
+    pub fn now(&self) -> u64 {
+        self.locks.now()
+    }
+
+    /// Operations waiting for others spin until a way to let them run is set,
+    /// such as yielding the thread.
+    pub fn set_park(&mut self, park: fn()) {
+        self.locks.set_park(park);
     }
 
     pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
+        Self::disk_inode_pos(self.inode_area_start_block, inode_id)
+    }
+
+    pub(crate) fn disk_inode_pos(inode_area_start_block: u32, inode_id: u32) -> (u32, usize) {
         let inode_size = core::mem::size_of::<DiskInode>();
         let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
-        let block_id = self.inode_area_start_block + inode_id / inodes_per_block;
+        let block_id = inode_area_start_block + inode_id / inodes_per_block;
         (
             block_id,
             (inode_id % inodes_per_block) as usize * inode_size,
@@ -122,23 +260,39 @@ impl EasyFileSystem {
         self.data_area_start_block + data_block_id
     }
 
-    pub fn alloc_inode(&mut self) -> u32 {
-        self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
+    /// Return `None` if every inode is in use.
+    pub fn alloc_inode(&mut self) -> Option<u32> {
+        self.inode_bitmap
+            .alloc(&self.block_device)
+            .map(|inode_id| inode_id as u32)
     }
 
-    /// Return a block ID not ID in the data area.
-    pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
     }
 
-    pub fn dealloc_data(&mut self, block_id: u32) {
+    /// Return a block ID not ID in the data area, or `None` if the data area is full.
+    /// The block is cleared here, so freeing it doesn't touch its content.
+    pub fn alloc_data(&mut self) -> Option<u32> {
+        let bit = self.data_bitmap.alloc(&self.block_device)?;
+        if bit >= self.data_area_blocks as usize {
+            // the first free bit lies past the data area
+            self.data_bitmap.dealloc(&self.block_device, bit);
+            return None;
+        }
+        let block_id = bit as u32 + self.data_area_start_block;
         get_block_cache(block_id as usize, Arc::clone(&self.block_device))
             .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            .modify_data(0, |data_block: &mut DataBlock| {
                 data_block.iter_mut().for_each(|p| {
                     *p = 0;
                 })
             });
+        Some(block_id)
+    }
+
+    pub fn dealloc_data(&mut self, block_id: u32) {
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize,
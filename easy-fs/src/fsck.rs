@@ -0,0 +1,315 @@
+use super::{
+    get_block_cache, BlockDevice, DirEntry, DiskInode, EasyFileSystem, SuperBlock, BLOCK_SZ,
+};
+use alloc::collections::btree_map::Entry;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Result};
+
+/// A problem `fsck` found in an image.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The magic number, a feature bit or the area sizes are wrong, nothing else is checked.
+    BadSuperBlock,
+    /// An inode reachable from the root is free in the inode bitmap.
+    InodeNotAllocated(u32),
+    /// An allocated inode no dirent reachable from the root points to.
+    OrphanInode(u32),
+    /// The link count of an inode differs from the dirents pointing to it.
+    WrongLinkCount {
+        inode_id: u32,
+        nlink: u32,
+        links: u32,
+    },
+    /// A dirent points outside of the inode area.
+    BadDirent {
+        dir: u32,
+        name: String,
+        inode_id: u32,
+    },
+    /// An inode uses a block outside of the data area, its blocks are not checked further.
+    BadBlock { inode_id: u32, block_id: u32 },
+    /// A block used twice, `inode_id` is the second user.
+    DuplicateBlock { inode_id: u32, block_id: u32 },
+    /// A block in use is free in the data bitmap.
+    BlockNotAllocated { inode_id: u32, block_id: u32 },
+    /// An allocated data block no inode uses.
+    OrphanBlock(u32),
+}
+
+impl FsckProblem {
+    /// Damaged superblocks, bad blocks and duplicate blocks need a human.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(
+            self,
+            Self::BadSuperBlock | Self::BadBlock { .. } | Self::DuplicateBlock { .. }
+        )
+    }
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::BadSuperBlock => write!(f, "bad superblock"),
+            Self::InodeNotAllocated(inode_id) => {
+                write!(f, "inode {} is in use but not allocated", inode_id)
+            }
+            Self::OrphanInode(inode_id) => write!(f, "inode {} is not reachable", inode_id),
+            Self::WrongLinkCount {
+                inode_id,
+                nlink,
+                links,
+            } => write!(
+                f,
+                "inode {} has nlink {} but {} links",
+                inode_id, nlink, links
+            ),
+            Self::BadDirent {
+                dir,
+                name,
+                inode_id,
+            } => write!(
+                f,
+                "dirent {} of inode {} points to bad inode {}",
+                name, dir, inode_id
+            ),
+            Self::BadBlock { inode_id, block_id } => {
+                write!(f, "inode {} uses bad block {}", inode_id, block_id)
+            }
+            Self::DuplicateBlock { inode_id, block_id } => {
+                write!(f, "inode {} uses block {} used before", inode_id, block_id)
+            }
+            Self::BlockNotAllocated { inode_id, block_id } => write!(
+                f,
+                "inode {} uses block {} which is not allocated",
+                inode_id, block_id
+            ),
+            Self::OrphanBlock(block_id) => write!(f, "block {} is allocated but unused", block_id),
+        }
+    }
+}
+
+/// Check the image on `block_device` by walking the directory tree from the root,
+/// a transaction left in the journal is installed first.
+///
+/// With `repair`, each repairable problem is fixed in an operation like those of an
+/// `Inode`, so no transaction holds part of a repair, and all is written back before
+/// returning: bitmaps and link counts are corrected, bad dirents are removed and
+/// orphan inodes are freed together with their blocks.
+pub fn fsck(block_device: Arc<dyn BlockDevice>, repair: bool) -> Vec<FsckProblem> {
+    let super_block = get_block_cache(0, Arc::clone(&block_device))
+        .lock()
+        .read(0, |super_block: &SuperBlock| *super_block);
+    if !super_block.is_valid() || !super_block.is_consistent() {
+        return vec![FsckProblem::BadSuperBlock];
+    }
+    let efs = EasyFileSystem::open(Arc::clone(&block_device));
+    let fs = efs.lock();
+    let inodes_per_block = BLOCK_SZ / core::mem::size_of::<DiskInode>();
+    let inode_count = fs
+        .inode_bitmap
+        .maximum()
+        .min(super_block.inode_area_blocks as usize * inodes_per_block);
+    let data_area_start = fs.get_data_block_id(0);
+    let data_area = data_area_start..data_area_start + super_block.data_area_blocks;
+    let dirent_format = fs.dirent_format();
+
+    let mut problems = Vec::new();
+    // dirents pointing to each inode and the nlink of the reachable ones
+    // counted wider than `nlink`, which may have wrapped
+    let mut links = vec![0u32; inode_count];
+    let mut nlinks: BTreeMap<u32, u32> = BTreeMap::new();
+    let mut owners: BTreeMap<u32, u32> = BTreeMap::new();
+    // the directory and the offset and length of the record
+    let mut bad_dirents: Vec<(u32, usize, usize)> = Vec::new();
+    let mut queue = VecDeque::from([0u32]);
+    nlinks.insert(0, 0);
+    while let Some(inode_id) = queue.pop_front() {
+        if !fs.inode_bitmap.is_set(&block_device, inode_id as usize) {
+            problems.push(FsckProblem::InodeNotAllocated(inode_id));
+        }
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let dirents = get_block_cache(block_id as usize, Arc::clone(&block_device))
+            .lock()
+            .read(block_offset, |disk_inode: &DiskInode| {
+                nlinks.insert(inode_id, disk_inode.nlink as u32);
+                let blocks = match disk_inode
+                    .blocks(|block_id| data_area.contains(&block_id), &block_device)
+                {
+                    Ok(blocks) => blocks,
+                    Err(block_id) => {
+                        problems.push(FsckProblem::BadBlock { inode_id, block_id });
+                        return Vec::new();
+                    }
+                };
+                for block_id in blocks {
+                    if owners.insert(block_id, inode_id).is_some() {
+                        problems.push(FsckProblem::DuplicateBlock { inode_id, block_id });
+                    }
+                    let bit = (block_id - data_area_start) as usize;
+                    if !fs.data_bitmap.is_set(&block_device, bit) {
+                        problems.push(FsckProblem::BlockNotAllocated { inode_id, block_id });
+                    }
+                }
+                if !disk_inode.is_dir() {
+                    return Vec::new();
+                }
+                let mut content = vec![0u8; disk_inode.size as usize];
+                disk_inode.read_at(0, &mut content, &block_device);
+                dirent_format
+                    .decode(&content)
+                    .into_iter()
+                    .filter(|(_, _, dirent)| !dirent.is_empty())
+                    .collect()
+            });
+        for (offset, len, dirent) in dirents {
+            let child = dirent.inode_number();
+            if child as usize >= inode_count {
+                problems.push(FsckProblem::BadDirent {
+                    dir: inode_id,
+                    name: String::from(dirent.name()),
+                    inode_id: child,
+                });
+                bad_dirents.push((inode_id, offset, len));
+                continue;
+            }
+            links[child as usize] += 1;
+            if let Entry::Vacant(entry) = nlinks.entry(child) {
+                entry.insert(0);
+                queue.push_back(child);
+            }
+        }
+    }
+    for (&inode_id, &nlink) in nlinks.iter() {
+        let links = links[inode_id as usize];
+        if nlink != links {
+            problems.push(FsckProblem::WrongLinkCount {
+                inode_id,
+                nlink,
+                links,
+            });
+        }
+    }
+    for inode_id in 0..inode_count as u32 {
+        if !nlinks.contains_key(&inode_id)
+            && fs.inode_bitmap.is_set(&block_device, inode_id as usize)
+        {
+            problems.push(FsckProblem::OrphanInode(inode_id));
+        }
+    }
+    for block_id in data_area {
+        let bit = (block_id - data_area_start) as usize;
+        if !owners.contains_key(&block_id) && fs.data_bitmap.is_set(&block_device, bit) {
+            problems.push(FsckProblem::OrphanBlock(block_id));
+        }
+    }
+
+    if repair {
+        // each repair is an operation of its own, changing a block or two
+        let locks = fs.locks();
+        drop(fs);
+        for problem in problems.iter() {
+            let _op = locks.begin_op();
+            let fs = efs.lock();
+            match *problem {
+                FsckProblem::InodeNotAllocated(inode_id) => {
+                    fs.inode_bitmap.set(&block_device, inode_id as usize)
+                }
+                FsckProblem::OrphanInode(inode_id) => {
+                    fs.inode_bitmap.dealloc(&block_device, inode_id as usize)
+                }
+                FsckProblem::WrongLinkCount {
+                    inode_id, links, ..
+                } => {
+                    let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+                    get_block_cache(block_id as usize, Arc::clone(&block_device))
+                        .lock()
+                        .modify(block_offset, |disk_inode: &mut DiskInode| {
+                            disk_inode.nlink = links.min(u16::MAX as u32) as u16;
+                        });
+                }
+                FsckProblem::BlockNotAllocated { block_id, .. } => fs
+                    .data_bitmap
+                    .set(&block_device, (block_id - data_area_start) as usize),
+                FsckProblem::OrphanBlock(block_id) => fs
+                    .data_bitmap
+                    .dealloc(&block_device, (block_id - data_area_start) as usize),
+                _ => {}
+            }
+        }
+        for &(dir, offset, len) in bad_dirents.iter() {
+            let mut buf = vec![0u8; len];
+            let bytes = dirent_format.encode(&DirEntry::empty(), len, &mut buf);
+            // the head of the record removes it, however long the record claims to be
+            let bytes = &bytes[..dirent_format.record_len(0)];
+            let _op = locks.begin_op();
+            let fs = efs.lock();
+            let (block_id, block_offset) = fs.get_disk_inode_pos(dir);
+            get_block_cache(block_id as usize, Arc::clone(&block_device))
+                .lock()
+                .modify(block_offset, |disk_inode: &mut DiskInode| {
+                    disk_inode.write_at(offset, bytes, &block_device);
+                });
+        }
+        locks.sync();
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn repairable_problems() {
+        assert!(!FsckProblem::BadSuperBlock.is_repairable());
+        assert!(!FsckProblem::BadBlock {
+            inode_id: 1,
+            block_id: 2
+        }
+        .is_repairable());
+        assert!(!FsckProblem::DuplicateBlock {
+            inode_id: 1,
+            block_id: 2
+        }
+        .is_repairable());
+        assert!(FsckProblem::OrphanInode(1).is_repairable());
+        assert!(FsckProblem::BadDirent {
+            dir: 0,
+            name: String::from("a"),
+            inode_id: 9,
+        }
+        .is_repairable());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(FsckProblem::BadSuperBlock.to_string(), "bad superblock");
+        assert_eq!(
+            FsckProblem::WrongLinkCount {
+                inode_id: 3,
+                nlink: 1,
+                links: 2
+            }
+            .to_string(),
+            "inode 3 has nlink 1 but 2 links"
+        );
+        assert_eq!(
+            FsckProblem::BadDirent {
+                dir: 0,
+                name: String::from("a"),
+                inode_id: 9,
+            }
+            .to_string(),
+            "dirent a of inode 0 points to bad inode 9"
+        );
+        assert_eq!(
+            FsckProblem::OrphanBlock(7).to_string(),
+            "block 7 is allocated but unused"
+        );
+    }
+}
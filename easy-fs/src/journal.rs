@@ -0,0 +1,110 @@
+use super::{get_block_cache, BlockDevice, BLOCK_SZ};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+const JOURNAL_MAGIC: u32 = 0x4a524e4c;
+const HEADER_BLOCK_IDS: usize = BLOCK_SZ / 4 - 2;
+
+/// First block of the journal, a non-zero `count` means a committed transaction
+/// whose blocks are logged in the following journal blocks.
+#[repr(C)]
+struct JournalHeader {
+    magic: u32,
+    count: u32,
+    block_ids: [u32; HEADER_BLOCK_IDS],
+}
+
+type DataBlock = [u8; BLOCK_SZ];
+
+impl JournalHeader {
+    fn new(block_ids: &[u32]) -> Self {
+        let mut header = Self {
+            magic: JOURNAL_MAGIC,
+            count: block_ids.len() as u32,
+            block_ids: [0; HEADER_BLOCK_IDS],
+        };
+        header.block_ids[..block_ids.len()].copy_from_slice(block_ids);
+        header
+    }
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, BLOCK_SZ) }
+    }
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as *mut u8, BLOCK_SZ) }
+    }
+}
+
+/// A write-ahead log of whole blocks.
+///
+/// The journal is accessed without the block cache, writing its header block
+/// is the commit point of a transaction.
+#[derive(Clone, Copy, Debug)]
+pub struct Journal {
+    start_block: u32,
+    blocks: u32,
+}
+
+impl Journal {
+    pub fn new(start_block: u32, blocks: u32) -> Self {
+        assert!(blocks >= 2);
+        Self {
+            start_block,
+            blocks,
+        }
+    }
+
+    /// Blocks occupied by the journal.
+    pub fn region(&self) -> Range<usize> {
+        self.start_block as usize..(self.start_block + self.blocks) as usize
+    }
+
+    /// Blocks a transaction can hold at most.
+    pub fn capacity(&self) -> usize {
+        (self.blocks as usize - 1).min(HEADER_BLOCK_IDS)
+    }
+
+    /// Log the new contents of the blocks in `updates` and commit them.
+    /// The blocks must be written to their place afterwards, then call `clear`.
+    pub fn log(&self, block_device: &Arc<dyn BlockDevice>, updates: &[(usize, &[u8])]) {
+        assert!(updates.len() <= self.capacity(), "Transaction too large!");
+        for (i, (_, data)) in updates.iter().enumerate() {
+            block_device.write_block(self.start_block as usize + 1 + i, data);
+        }
+        let block_ids: Vec<u32> = updates.iter().map(|(id, _)| *id as u32).collect();
+        block_device.write_block(
+            self.start_block as usize,
+            JournalHeader::new(&block_ids).as_bytes(),
+        );
+    }
+
+    /// Mark the committed transaction as installed.
+    pub fn clear(&self, block_device: &Arc<dyn BlockDevice>) {
+        block_device.write_block(
+            self.start_block as usize,
+            JournalHeader::new(&[]).as_bytes(),
+        );
+    }
+
+    /// Install the transaction a crash left committed but not installed,
+    /// return the number of blocks written.
+    pub fn replay(&self, block_device: &Arc<dyn BlockDevice>) -> usize {
+        let mut header = JournalHeader::new(&[]);
+        block_device.read_block(self.start_block as usize, header.as_bytes_mut());
+        if header.magic != JOURNAL_MAGIC || header.count == 0 {
+            return 0;
+        }
+        let count = (header.count as usize).min(self.capacity());
+        let mut data = [0u8; BLOCK_SZ];
+        for (i, block_id) in header.block_ids[..count].iter().enumerate() {
+            block_device.read_block(self.start_block as usize + 1 + i, &mut data);
+            // go through the cache so it doesn't keep a stale copy
+            let block_cache = get_block_cache(*block_id as usize, Arc::clone(block_device));
+            let mut block_cache = block_cache.lock();
+            block_cache.modify_data(0, |block: &mut DataBlock| block.copy_from_slice(&data));
+            block_cache.sync();
+        }
+        self.clear(block_device);
+        count
+    }
+}
@@ -1,11 +1,20 @@
-use super::{get_block_cache, BlockDevice, BLOCK_SZ};
+use super::{get_block_cache, BlockDevice, Journal, BLOCK_SZ};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::convert::TryInto;
 use core::fmt::{Debug, Formatter, Result};
 
-const EFS_MAGIC: u32 = 0x3b800001;
-const INODE_DIRECT_COUNT: usize = 28;
+/// Changed with the layout of `DiskInode`, so images of the old one are refused.
+const EFS_MAGIC: u32 = 0x3b800002;
+/// Keeps `DiskInode` at 128 bytes.
+const INODE_DIRECT_COUNT: usize = 22;
 const NAME_LENGTH_LIMIT: usize = 27;
+const LONG_NAME_LENGTH_LIMIT: usize = 255;
+/// Directories use `DirEntryFormat::Long`.
+pub const FEATURE_LONG_NAMES: u32 = 1;
+/// The last `journal_blocks` blocks are a `Journal`.
+pub const FEATURE_JOURNAL: u32 = 1 << 1;
+const SUPPORTED_FEATURES: u32 = FEATURE_LONG_NAMES | FEATURE_JOURNAL;
 const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
 const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
@@ -14,6 +23,7 @@ const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
 const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct SuperBlock {
     magic: u32,
     pub total_blocks: u32,
@@ -21,6 +31,9 @@ pub struct SuperBlock {
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    /// `FEATURE_*` bits.
+    pub features: u32,
+    pub journal_blocks: u32,
 }
 
 impl Debug for SuperBlock {
@@ -31,11 +44,14 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("features", &self.features)
+            .field("journal_blocks", &self.journal_blocks)
             .finish()
     }
 }
 
 impl SuperBlock {
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize(
         &mut self,
         total_blocks: u32,
@@ -43,6 +59,8 @@ impl SuperBlock {
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        features: u32,
+        journal_blocks: u32,
     ) {
         *self = Self {
             magic: EFS_MAGIC,
@@ -51,14 +69,45 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            features,
+            journal_blocks,
         }
     }
+    /// Images with features we don't know about are rejected.
     pub fn is_valid(&self) -> bool {
-        self.magic == EFS_MAGIC
+        self.magic == EFS_MAGIC && self.features & !SUPPORTED_FEATURES == 0
+    }
+    /// The areas add up to the whole image.
+    pub fn is_consistent(&self) -> bool {
+        let blocks = 1
+            + self.inode_bitmap_blocks as u64
+            + self.inode_area_blocks as u64
+            + self.data_bitmap_blocks as u64
+            + self.data_area_blocks as u64
+            + self.journal_blocks as u64;
+        blocks == self.total_blocks as u64
+            && self.data_bitmap_blocks as usize * BLOCK_SZ * 8 >= self.data_area_blocks as usize
+    }
+    pub fn journal(&self) -> Option<Journal> {
+        if self.features & FEATURE_JOURNAL != 0 {
+            Some(Journal::new(
+                self.total_blocks - self.journal_blocks,
+                self.journal_blocks,
+            ))
+        } else {
+            None
+        }
+    }
+    pub fn dirent_format(&self) -> DirEntryFormat {
+        if self.features & FEATURE_LONG_NAMES != 0 {
+            DirEntryFormat::Long
+        } else {
+            DirEntryFormat::Short
+        }
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum DiskInodeType {
     File,
     Directory,
@@ -68,22 +117,46 @@ type IndirectBlock = [u32; BLOCK_SZ / 4];
 type DataBlock = [u8; BLOCK_SZ];
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct DiskInode {
     pub size: u32,
     pub direct: [u32; INODE_DIRECT_COUNT],
     pub indirect1: u32,
     pub indirect2: u32,
     type_: DiskInodeType,
+    /// Number of dirents pointing to this inode.
+    pub nlink: u16,
+    /// Permission bits, they are not checked by easy-fs.
+    pub mode: u16,
+    /// Last modification of the content.
+    pub mtime: u64,
+    /// Last change of the content or the metadata.
+    pub ctime: u64,
 }
 
 impl DiskInode {
     /// indirect1 and indirect2 block are allocated only when they are needed.
-    pub fn initialize(&mut self, type_: DiskInodeType) {
+    pub fn initialize(&mut self, type_: DiskInodeType, now: u64) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        self.mode = if type_ == DiskInodeType::Directory {
+            0o755
+        } else {
+            0o644
+        };
         self.type_ = type_;
+        self.nlink = 0;
+        self.mtime = now;
+        self.ctime = now;
+    }
+    /// Record a change at `now`, `modified` for the content.
+    pub fn touch(&mut self, now: u64, modified: bool) {
+        if modified {
+            self.mtime = now;
+        }
+        self.ctime = now;
     }
     pub fn is_dir(&self) -> bool {
         self.type_ == DiskInodeType::Directory
@@ -217,6 +290,55 @@ impl DiskInode {
             });
     }
 
+    /// Return all the blocks in use, indirect blocks included, or the first block
+    /// `is_valid` rejects. Rejected indirect blocks are not read.
+    pub fn blocks(
+        &self,
+        is_valid: impl Fn(u32) -> bool,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> core::result::Result<Vec<u32>, u32> {
+        let mut v: Vec<u32> = Vec::new();
+        let mut push = |block_id: u32| {
+            if is_valid(block_id) {
+                v.push(block_id);
+                Ok(())
+            } else {
+                Err(block_id)
+            }
+        };
+        let read_indirect = |block_id: u32| {
+            get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .read(0, |indirect_block: &IndirectBlock| *indirect_block)
+        };
+        let data_blocks = self.data_blocks() as usize;
+        for &block_id in self.direct.iter().take(data_blocks) {
+            push(block_id)?;
+        }
+        if data_blocks > INODE_DIRECT_COUNT {
+            let data_blocks = data_blocks - INODE_DIRECT_COUNT;
+            push(self.indirect1)?;
+            for &block_id in read_indirect(self.indirect1).iter().take(data_blocks) {
+                push(block_id)?;
+            }
+            if data_blocks > INODE_INDIRECT1_COUNT {
+                let data_blocks = data_blocks - INODE_INDIRECT1_COUNT;
+                push(self.indirect2)?;
+                let indirect2 = read_indirect(self.indirect2);
+                let indirect1_count =
+                    (data_blocks + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+                for (i, &indirect1) in indirect2.iter().take(indirect1_count).enumerate() {
+                    push(indirect1)?;
+                    let count = data_blocks - i * INODE_INDIRECT1_COUNT;
+                    for &block_id in read_indirect(indirect1).iter().take(count) {
+                        push(block_id)?;
+                    }
+                }
+            }
+        }
+        Ok(v)
+    }
+
     /// Clear size to zero and return blocks that should be deallocated.
     ///
     /// We will clear the block contents to zero later.
@@ -242,7 +364,7 @@ impl DiskInode {
         // indirect1
         get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
             .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
+            .read(0, |indirect1: &IndirectBlock| {
                 while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
                     v.push(indirect1[current_blocks]);
                     //indirect1[current_blocks] = 0;
@@ -263,13 +385,13 @@ impl DiskInode {
         let b1 = data_blocks % INODE_INDIRECT1_COUNT;
         get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
             .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
+            .read(0, |indirect2: &IndirectBlock| {
                 // full indirect1 blocks
-                for entry in indirect2.iter_mut().take(a1) {
+                for entry in indirect2.iter().take(a1) {
                     v.push(*entry);
                     get_block_cache(*entry as usize, Arc::clone(block_device))
                         .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
+                        .read(0, |indirect1: &IndirectBlock| {
                             for entry in indirect1.iter() {
                                 v.push(*entry);
                             }
@@ -280,7 +402,7 @@ impl DiskInode {
                     v.push(indirect2[a1]);
                     get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
                         .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
+                        .read(0, |indirect1: &IndirectBlock| {
                             for entry in indirect1.iter().take(b1) {
                                 v.push(*entry);
                             }
@@ -331,6 +453,7 @@ impl DiskInode {
         read_size
     }
     /// File size must be adjusted before.
+    /// Dirents are journaled, the content of a regular file is not.
     pub fn write_at(
         &mut self,
         offset: usize,
@@ -348,16 +471,20 @@ impl DiskInode {
             end_current_block = end_current_block.min(end);
             // write and update write size
             let block_write_size = end_current_block - start;
-            get_block_cache(
+            let block_cache = get_block_cache(
                 self.get_block_id(start_block as u32, block_device) as usize,
                 Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            );
+            let write = |data_block: &mut DataBlock| {
                 let src = &buf[write_size..write_size + block_write_size];
                 let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
                 dst.copy_from_slice(src);
-            });
+            };
+            if self.is_dir() {
+                block_cache.lock().modify(0, write);
+            } else {
+                block_cache.lock().modify_data(0, write);
+            }
             write_size += block_write_size;
             // move to next block
             if end_current_block == end {
@@ -370,34 +497,134 @@ impl DiskInode {
     }
 }
 
-#[repr(C)]
+/// How the dirents of an image are stored, chosen by `FEATURE_LONG_NAMES`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DirEntryFormat {
+    /// 32 bytes, a `\0` padded name up to 27 bytes followed by the inode number.
+    Short,
+    /// Records of the inode number, the record length as `u16`, the name length
+    /// as `u8` and a spare byte, followed by the name up to 255 bytes and padded
+    /// to 4 bytes. A removed record keeps its length and has an empty name.
+    Long,
+}
+
+const LONG_HEADER_SZ: usize = 8;
+
+impl DirEntryFormat {
+    pub fn name_limit(self) -> usize {
+        match self {
+            Self::Short => NAME_LENGTH_LIMIT,
+            Self::Long => LONG_NAME_LENGTH_LIMIT,
+        }
+    }
+    /// Size of the record of a dirent with a `name_len` bytes name.
+    pub fn record_len(self, name_len: usize) -> usize {
+        match self {
+            Self::Short => NAME_LENGTH_LIMIT + 1 + 4,
+            Self::Long => (LONG_HEADER_SZ + name_len).next_multiple_of(4),
+        }
+    }
+    /// Decode the records of the directory content `bytes`, removed ones included,
+    /// as their offset, length and dirent. Decoding stops at a damaged record.
+    pub fn decode(self, bytes: &[u8]) -> Vec<(usize, usize, DirEntry)> {
+        let mut v = Vec::new();
+        let mut offset = 0usize;
+        while offset < bytes.len() {
+            let rest = &bytes[offset..];
+            let (len, name, inode_number) = match self {
+                Self::Short => {
+                    let len = self.record_len(0);
+                    if rest.len() < len {
+                        break;
+                    }
+                    let name = &rest[..NAME_LENGTH_LIMIT + 1];
+                    let name_len = match name.iter().position(|&byte| byte == 0) {
+                        Some(name_len) => name_len,
+                        None => break,
+                    };
+                    (len, &name[..name_len], &rest[NAME_LENGTH_LIMIT + 1..len])
+                }
+                Self::Long => {
+                    if rest.len() < LONG_HEADER_SZ {
+                        break;
+                    }
+                    let len = u16::from_ne_bytes(rest[4..6].try_into().unwrap()) as usize;
+                    let name_len = rest[6] as usize;
+                    if len % 4 != 0 || len < self.record_len(name_len) || len > rest.len() {
+                        break;
+                    }
+                    (
+                        len,
+                        &rest[LONG_HEADER_SZ..LONG_HEADER_SZ + name_len],
+                        &rest[..4],
+                    )
+                }
+            };
+            let name = match core::str::from_utf8(name) {
+                Ok(name) => name,
+                Err(_) => break,
+            };
+            let inode_number = u32::from_ne_bytes(inode_number.try_into().unwrap());
+            v.push((offset, len, DirEntry::new(name, inode_number)));
+            offset += len;
+        }
+        v
+    }
+    /// Encode `dirent` as a record of `len` bytes into the start of `buf`,
+    /// the name must fit in it.
+    pub fn encode<'a>(self, dirent: &DirEntry, len: usize, buf: &'a mut [u8]) -> &'a [u8] {
+        let name = dirent.name().as_bytes();
+        assert!(name.len() <= self.name_limit() && self.record_len(name.len()) <= len);
+        let record = &mut buf[..len];
+        record.fill(0);
+        match self {
+            Self::Short => {
+                record[..name.len()].copy_from_slice(name);
+                record[NAME_LENGTH_LIMIT + 1..].copy_from_slice(&dirent.inode_number.to_ne_bytes());
+            }
+            Self::Long => {
+                record[..4].copy_from_slice(&dirent.inode_number.to_ne_bytes());
+                record[4..6].copy_from_slice(&(len as u16).to_ne_bytes());
+                record[6] = name.len() as u8;
+                record[LONG_HEADER_SZ..LONG_HEADER_SZ + name.len()].copy_from_slice(name);
+            }
+        }
+        record
+    }
+}
+
+/// A dirent in memory, it is large enough for both formats.
 pub struct DirEntry {
-    name: [u8; NAME_LENGTH_LIMIT + 1],
+    name: [u8; LONG_NAME_LENGTH_LIMIT + 1],
     inode_number: u32,
 }
 
-pub const DIRENT_SZ: usize = 32;
-
 impl DirEntry {
     pub fn empty() -> Self {
         Self {
-            name: [0u8; NAME_LENGTH_LIMIT + 1],
+            name: [0u8; LONG_NAME_LENGTH_LIMIT + 1],
             inode_number: 0,
         }
     }
     pub fn new(name: &str, inode_number: u32) -> Self {
-        let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
+        let mut bytes = [0u8; LONG_NAME_LENGTH_LIMIT + 1];
         bytes[..name.len()].copy_from_slice(name.as_bytes());
         Self {
             name: bytes,
             inode_number,
         }
     }
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, DIRENT_SZ) }
+    /// A removed dirent has an empty name.
+    pub fn is_empty(&self) -> bool {
+        self.name[0] == 0
     }
-    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
-        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, DIRENT_SZ) }
+    pub fn is_valid_name(name: &str, format: DirEntryFormat) -> bool {
+        !name.is_empty()
+            && name.len() <= format.name_limit()
+            && name != "."
+            && name != ".."
+            && !name.contains('/')
+            && !name.contains('\0')
     }
     pub fn name(&self) -> &str {
         let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
@@ -407,3 +634,140 @@ impl DirEntry {
         self.inode_number
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn total_blocks_count_index_blocks() {
+        assert_eq!(DiskInode::total_blocks(0), 0);
+        assert_eq!(DiskInode::total_blocks(1), 1);
+        assert_eq!(
+            DiskInode::total_blocks((DIRECT_BOUND * BLOCK_SZ) as u32),
+            22
+        );
+        assert_eq!(
+            DiskInode::total_blocks((DIRECT_BOUND * BLOCK_SZ + 1) as u32),
+            22 + 1 + 1
+        );
+        assert_eq!(
+            DiskInode::total_blocks((INDIRECT1_BOUND * BLOCK_SZ) as u32),
+            INDIRECT1_BOUND as u32 + 1
+        );
+        // the indirect2 block and one indirect1 block under it
+        assert_eq!(
+            DiskInode::total_blocks((INDIRECT1_BOUND * BLOCK_SZ + 1) as u32),
+            INDIRECT1_BOUND as u32 + 1 + 1 + 2
+        );
+    }
+
+    #[test]
+    fn super_block_checks() {
+        let mut super_block = SuperBlock {
+            magic: 0,
+            total_blocks: 0,
+            inode_bitmap_blocks: 0,
+            inode_area_blocks: 0,
+            data_bitmap_blocks: 0,
+            data_area_blocks: 0,
+            features: 0,
+            journal_blocks: 0,
+        };
+        assert!(!super_block.is_valid());
+        super_block.initialize(4096, 1, 1024, 1, 3005, FEATURE_JOURNAL, 64);
+        assert!(super_block.is_valid() && super_block.is_consistent());
+        assert_eq!(super_block.dirent_format(), DirEntryFormat::Short);
+        assert!(super_block.journal().is_some());
+        super_block.features = 1 << 31;
+        assert!(!super_block.is_valid());
+        super_block.features = FEATURE_LONG_NAMES;
+        assert_eq!(super_block.dirent_format(), DirEntryFormat::Long);
+        assert!(super_block.journal().is_none());
+        super_block.data_area_blocks += 1;
+        assert!(!super_block.is_consistent());
+        // one bitmap block covers `BLOCK_SZ * 8` blocks
+        super_block.initialize(1 + 1 + 1 + 1 + 4097, 1, 1, 1, 4097, 0, 0);
+        assert!(!super_block.is_consistent());
+    }
+
+    #[test]
+    fn record_len() {
+        assert_eq!(DirEntryFormat::Short.record_len(0), 32);
+        assert_eq!(DirEntryFormat::Short.record_len(27), 32);
+        assert_eq!(DirEntryFormat::Long.record_len(0), 8);
+        assert_eq!(DirEntryFormat::Long.record_len(1), 12);
+        assert_eq!(DirEntryFormat::Long.record_len(4), 12);
+        assert_eq!(DirEntryFormat::Long.record_len(255), 264);
+    }
+
+    #[test]
+    fn dirents_round_trip() {
+        for format in [DirEntryFormat::Short, DirEntryFormat::Long] {
+            let long_name = "x".repeat(format.name_limit());
+            let dirents = [
+                DirEntry::new(".", 3),
+                DirEntry::empty(),
+                DirEntry::new(&long_name, u32::MAX),
+            ];
+            let mut bytes = Vec::new();
+            for dirent in dirents.iter() {
+                // a removed record may be longer than it needs
+                let len = format
+                    .record_len(dirent.name().len())
+                    .max(format.record_len(3));
+                let mut buf = vec![0u8; len];
+                bytes.extend_from_slice(format.encode(dirent, len, &mut buf));
+            }
+            let decoded = format.decode(&bytes);
+            assert_eq!(decoded.len(), dirents.len());
+            let mut offset = 0;
+            for ((pos, len, decoded), dirent) in decoded.iter().zip(dirents.iter()) {
+                assert_eq!(*pos, offset);
+                assert_eq!(decoded.name(), dirent.name());
+                assert_eq!(decoded.inode_number(), dirent.inode_number());
+                offset += len;
+            }
+            assert_eq!(offset, bytes.len());
+            assert!(decoded[1].2.is_empty());
+        }
+    }
+
+    #[test]
+    fn decode_stops_at_damaged_record() {
+        let format = DirEntryFormat::Long;
+        let mut bytes = vec![0u8; 36];
+        format.encode(&DirEntry::new("a", 1), 12, &mut bytes[..12]);
+        format.encode(&DirEntry::new("b", 2), 12, &mut bytes[12..24]);
+        format.encode(&DirEntry::new("c", 3), 12, &mut bytes[24..]);
+        assert_eq!(format.decode(&bytes).len(), 3);
+        // a record length too short for the name
+        bytes[16..18].copy_from_slice(&8u16.to_ne_bytes());
+        assert_eq!(format.decode(&bytes).len(), 1);
+        // or past the end of the directory
+        bytes[16..18].copy_from_slice(&40u16.to_ne_bytes());
+        assert_eq!(format.decode(&bytes).len(), 1);
+        // or not a multiple of 4
+        bytes[16..18].copy_from_slice(&13u16.to_ne_bytes());
+        assert_eq!(format.decode(&bytes).len(), 1);
+        // a short name without its `\0`
+        let mut bytes = vec![b'x'; 32];
+        assert!(DirEntryFormat::Short.decode(&bytes).is_empty());
+        bytes[27] = 0;
+        assert_eq!(DirEntryFormat::Short.decode(&bytes).len(), 1);
+        assert!(DirEntryFormat::Short.decode(&bytes[..31]).is_empty());
+    }
+
+    #[test]
+    fn valid_names() {
+        assert!(DirEntry::is_valid_name("a", DirEntryFormat::Short));
+        assert!(!DirEntry::is_valid_name("", DirEntryFormat::Long));
+        assert!(!DirEntry::is_valid_name("..", DirEntryFormat::Long));
+        assert!(!DirEntry::is_valid_name("a/b", DirEntryFormat::Long));
+        assert!(!DirEntry::is_valid_name("a\0", DirEntryFormat::Long));
+        let name = "x".repeat(28);
+        assert!(!DirEntry::is_valid_name(&name, DirEntryFormat::Short));
+        assert!(DirEntry::is_valid_name(&name, DirEntryFormat::Long));
+    }
+}
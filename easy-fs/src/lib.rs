@@ -6,13 +6,24 @@ mod bitmap;
 mod block_cache;
 mod block_dev;
 mod efs;
+mod fsck;
+mod journal;
 mod layout;
+mod lock;
 mod vfs;
 
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+use block_cache::{block_cache_commit, block_cache_must_commit, get_block_cache, OPERATION_BLOCKS};
+pub use block_cache::{
+    block_cache_set_capacity, block_cache_set_policy, block_cache_stats, BlockCacheStats,
+    WritePolicy,
+};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
+pub use fsck::{fsck, FsckProblem};
+pub use journal::Journal;
+pub use layout::{DirEntryFormat, SuperBlock};
 use layout::*;
-pub use vfs::Inode;
+use lock::{FsLocks, Operation};
+pub use vfs::{DirEntryInfo, Inode, Metadata};
@@ -0,0 +1,202 @@
+use super::{block_cache_commit, block_cache_must_commit, BlockDevice, Journal, OPERATION_BLOCKS};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+fn no_clock() -> u64 {
+    0
+}
+
+fn spin() {
+    core::hint::spin_loop();
+}
+
+struct Operations {
+    /// Operations between `begin_op` and `end_op`.
+    outstanding: usize,
+    /// An operation ended wanting a commit, it happens when the others end.
+    commit_wanted: bool,
+    committing: bool,
+}
+
+/// The locks of an easy-fs, shared by its inodes so that only bitmap
+/// allocation needs the `EasyFileSystem` mutex.
+///
+/// Locks are taken in this order: an operation is begun first, then the
+/// namespace lock, then inode locks, the efs mutex and block caches last.
+pub struct FsLocks {
+    /// Taken exclusively to change directories, shared to look names up.
+    pub namespace: RwLock<()>,
+    /// The lock guarding the content of each inode with an `Inode` alive.
+    inodes: Mutex<BTreeMap<u32, Weak<RwLock<()>>>>,
+    /// Entries of `inodes` over which dead ones are dropped.
+    prune_at: Mutex<usize>,
+    /// Inodes whose last link went away while an `Inode` of them was alive.
+    orphans: Mutex<BTreeSet<u32>>,
+    /// Orphans whose last `Inode` was dropped, freed before the next operation.
+    reclaimable: Mutex<Vec<u32>>,
+    operations: Mutex<Operations>,
+    /// At most this many operations run at once, so that their changes fit in the journal.
+    max_operations: usize,
+    journal: Option<Journal>,
+    block_device: Arc<dyn BlockDevice>,
+    /// Source of the inode timestamps.
+    clock: RwLock<fn() -> u64>,
+    /// Called in a loop while waiting for other operations, it should let them run.
+    park: RwLock<fn()>,
+}
+
+impl FsLocks {
+    pub fn new(journal: Option<Journal>, block_device: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            namespace: RwLock::new(()),
+            inodes: Mutex::new(BTreeMap::new()),
+            prune_at: Mutex::new(64),
+            orphans: Mutex::new(BTreeSet::new()),
+            reclaimable: Mutex::new(Vec::new()),
+            operations: Mutex::new(Operations {
+                outstanding: 0,
+                commit_wanted: false,
+                committing: false,
+            }),
+            max_operations: journal.map_or(usize::MAX, |journal| {
+                (journal.capacity() / OPERATION_BLOCKS).max(1)
+            }),
+            journal,
+            block_device,
+            clock: RwLock::new(no_clock),
+            park: RwLock::new(spin),
+        }
+    }
+
+    /// Return the lock of `inode_id`, every `Inode` of the same inode gets the same lock.
+    pub fn inode(&self, inode_id: u32) -> Arc<RwLock<()>> {
+        let mut inodes = self.inodes.lock();
+        if let Some(lock) = inodes.get(&inode_id).and_then(Weak::upgrade) {
+            return lock;
+        }
+        let lock = Arc::new(RwLock::new(()));
+        inodes.insert(inode_id, Arc::downgrade(&lock));
+        let mut prune_at = self.prune_at.lock();
+        if inodes.len() >= *prune_at {
+            inodes.retain(|_, lock| lock.strong_count() > 0);
+            *prune_at = (inodes.len() * 2).max(64);
+        }
+        lock
+    }
+
+    /// The last link of `inode_id` went away, return true if an `Inode` of it other
+    /// than the one holding `lock` is alive, the inode is freed after they are dropped.
+    pub fn orphan_if_open(&self, inode_id: u32, lock: &Arc<RwLock<()>>) -> bool {
+        let _inodes = self.inodes.lock();
+        if Arc::strong_count(lock) > 1 {
+            self.orphans.lock().insert(inode_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drop the lock of an `Inode` going away, an orphan becomes reclaimable with its last one.
+    pub fn release_inode(&self, inode_id: u32, lock: Arc<RwLock<()>>) {
+        // no `Inode` of it is made meanwhile
+        let _inodes = self.inodes.lock();
+        let last = Arc::strong_count(&lock) == 1;
+        drop(lock);
+        if last && self.orphans.lock().remove(&inode_id) {
+            self.reclaimable.lock().push(inode_id);
+        }
+    }
+
+    pub fn take_reclaimable(&self) -> Option<u32> {
+        self.reclaimable.lock().pop()
+    }
+
+    pub fn set_clock(&self, clock: fn() -> u64) {
+        *self.clock.write() = clock;
+    }
+
+    pub fn now(&self) -> u64 {
+        (self.clock.read())()
+    }
+
+    pub fn set_park(&self, park: fn()) {
+        *self.park.write() = park;
+    }
+
+    fn park(&self) {
+        let park = *self.park.read();
+        park();
+    }
+
+    /// Start an operation changing the file system, it ends when the guard is dropped.
+    /// No lock may be held here, this waits while a commit is due or the journal
+    /// has no room for another operation.
+    pub fn begin_op(&self) -> Operation {
+        loop {
+            let mut operations = self.operations.lock();
+            if !operations.committing
+                && !operations.commit_wanted
+                && operations.outstanding < self.max_operations
+            {
+                operations.outstanding += 1;
+                return Operation { locks: self };
+            }
+            drop(operations);
+            self.park();
+        }
+    }
+
+    /// The last operation to end commits if the write policy asks for it,
+    /// so no transaction ever holds part of an operation.
+    fn end_op(&self) {
+        let mut operations = self.operations.lock();
+        operations.outstanding -= 1;
+        let reserve = self.max_operations.saturating_mul(OPERATION_BLOCKS);
+        if !operations.commit_wanted
+            && !block_cache_must_commit(self.journal.as_ref(), reserve, &self.block_device)
+        {
+            return;
+        }
+        if operations.outstanding > 0 {
+            operations.commit_wanted = true;
+            return;
+        }
+        operations.commit_wanted = false;
+        operations.committing = true;
+        drop(operations);
+        block_cache_commit(self.journal.as_ref(), &self.block_device);
+        self.operations.lock().committing = false;
+    }
+
+    /// Wait for the running operations and write back everything,
+    /// the caller must not be in an operation.
+    pub fn sync(&self) {
+        loop {
+            let mut operations = self.operations.lock();
+            if !operations.committing && operations.outstanding == 0 {
+                operations.commit_wanted = false;
+                operations.committing = true;
+                break;
+            }
+            // no new operation starts meanwhile
+            operations.commit_wanted = true;
+            drop(operations);
+            self.park();
+        }
+        block_cache_commit(self.journal.as_ref(), &self.block_device);
+        self.operations.lock().committing = false;
+    }
+}
+
+/// An operation begun with `FsLocks::begin_op`.
+pub struct Operation<'a> {
+    locks: &'a FsLocks,
+}
+
+impl Drop for Operation<'_> {
+    fn drop(&mut self) {
+        self.locks.end_op();
+    }
+}
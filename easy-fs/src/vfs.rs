@@ -1,30 +1,80 @@
 use super::{
-    block_cache_sync_all, get_block_cache, BlockDevice, DirEntry, DiskInode, DiskInodeType,
-    EasyFileSystem, DIRENT_SZ,
+    get_block_cache, BlockDevice, DirEntry, DirEntryFormat, DiskInode, DiskInodeType,
+    EasyFileSystem, FsLocks, Operation, BLOCK_SZ,
 };
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
-use spin::{Mutex, MutexGuard};
+use core::mem::ManuallyDrop;
+use spin::{Mutex, RwLock};
 
+/// `Inode::write_at` grows a file by at most this many bytes per transaction,
+/// which keeps the metadata it changes within the journal.
+const WRITE_CHUNK_SZ: usize = 32 * BLOCK_SZ;
+
+/// What `Inode::metadata` reports about an inode.
+pub struct Metadata {
+    pub inode_id: u32,
+    pub is_dir: bool,
+    /// Permission bits.
+    pub mode: u16,
+    pub size: u32,
+    pub nlink: u16,
+    /// Blocks in use, indirect blocks included.
+    pub blocks: u32,
+    pub mtime: u64,
+    pub ctime: u64,
+}
+
+/// A live dirent reported by `Inode::read_dir`.
+pub struct DirEntryInfo {
+    pub name: String,
+    pub inode_id: u32,
+    pub is_dir: bool,
+    /// Directory offset of the dirent after this one.
+    pub next_offset: usize,
+}
+
+/// Directories are changed under the namespace lock of the file system and the
+/// content of a file under its inode lock, the efs lock is only taken to
+/// allocate and free inodes and blocks.
 pub struct Inode {
+    inode_id: u32,
     block_id: usize,
     block_offset: usize,
+    /// Copied from the superblock so dirents can be read without the efs lock.
+    dirent_format: DirEntryFormat,
+    /// Copied too, other inodes are found without the efs lock.
+    inode_area_start_block: u32,
+    /// Taken shared to read the content and exclusively to change it,
+    /// released to `FsLocks` on drop.
+    lock: ManuallyDrop<Arc<RwLock<()>>>,
+    locks: Arc<FsLocks>,
     fs: Arc<Mutex<EasyFileSystem>>,
     block_device: Arc<dyn BlockDevice>,
 }
 
 impl Inode {
     /// We should not acquire efs lock here.
-    pub fn new(
-        block_id: u32,
-        block_offset: usize,
+    pub(crate) fn new(
+        inode_id: u32,
+        dirent_format: DirEntryFormat,
+        inode_area_start_block: u32,
         fs: Arc<Mutex<EasyFileSystem>>,
+        locks: Arc<FsLocks>,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
+        let (block_id, block_offset) =
+            EasyFileSystem::disk_inode_pos(inode_area_start_block, inode_id);
         Self {
+            inode_id,
             block_id: block_id as usize,
             block_offset,
+            dirent_format,
+            inode_area_start_block,
+            lock: ManuallyDrop::new(locks.inode(inode_id)),
+            locks,
             fs,
             block_device,
         }
@@ -42,145 +92,512 @@ impl Inode {
             .modify(self.block_offset, f)
     }
 
-    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
-        // assert it is a directory
-        assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-        let mut dirent = DirEntry::empty();
-        for i in 0..file_count {
-            assert_eq!(
-                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device,),
-                DIRENT_SZ,
-            );
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
-            }
-        }
-        None
+    /// A copy of the disk inode, read without holding its block
+    /// while the blocks it points to are read.
+    fn disk_inode(&self) -> DiskInode {
+        self.read_disk_inode(|disk_inode| *disk_inode)
     }
 
-    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode).map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
+    pub fn inode_id(&self) -> u32 {
+        self.inode_id
+    }
+
+    pub fn nlink(&self) -> u16 {
+        self.read_disk_inode(|disk_inode| disk_inode.nlink)
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.read_disk_inode(|disk_inode| Metadata {
+            inode_id: self.inode_id,
+            is_dir: disk_inode.is_dir(),
+            mode: disk_inode.mode,
+            size: disk_inode.size,
+            nlink: disk_inode.nlink,
+            blocks: DiskInode::total_blocks(disk_inode.size),
+            mtime: disk_inode.mtime,
+            ctime: disk_inode.ctime,
         })
     }
 
-    fn increase_size(
+    pub fn is_dir(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+
+    /// Read all the dirents of a directory, removed ones included,
+    /// with the offset and length of their records.
+    fn dirents(&self, disk_inode: &DiskInode) -> Vec<(usize, usize, DirEntry)> {
+        // assert it is a directory
+        assert!(disk_inode.is_dir());
+        let mut content = vec![0u8; disk_inode.size as usize];
+        disk_inode.read_at(0, &mut content, &self.block_device);
+        self.dirent_format.decode(&content)
+    }
+
+    /// Write `dirent` as the record of `len` bytes at `offset`.
+    fn write_dirent(
         &self,
-        new_size: u32,
+        offset: usize,
+        len: usize,
+        dirent: &DirEntry,
         disk_inode: &mut DiskInode,
-        fs: &mut MutexGuard<EasyFileSystem>,
     ) {
+        let mut buf = vec![0u8; len];
+        let bytes = self.dirent_format.encode(dirent, len, &mut buf);
+        disk_inode.write_at(offset, bytes, &self.block_device);
+    }
+
+    fn is_valid_name(&self, name: &str) -> bool {
+        DirEntry::is_valid_name(name, self.dirent_format)
+    }
+
+    /// Return the offset and length of the record of the dirent called `name`
+    /// and its inode id.
+    fn find_dirent(&self, name: &str, disk_inode: &DiskInode) -> Option<((usize, usize), u32)> {
+        self.dirents(disk_inode)
+            .iter()
+            .find(|(_, _, dirent)| !dirent.is_empty() && dirent.name() == name)
+            .map(|(offset, len, dirent)| ((*offset, *len), dirent.inode_number()))
+    }
+
+    fn get_inode(&self, inode_id: u32) -> Arc<Inode> {
+        Arc::new(Self::new(
+            inode_id,
+            self.dirent_format,
+            self.inode_area_start_block,
+            self.fs.clone(),
+            self.locks.clone(),
+            self.block_device.clone(),
+        ))
+    }
+
+    /// Return `None` if it is not found or `self` is not a directory.
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let _namespace = self.locks.namespace.read();
+        self.lookup(name).map(|(_, inode)| inode)
+    }
+
+    /// Return false and leave the size alone if there are not enough free blocks.
+    fn increase_size(&self, new_size: u32, disk_inode: &mut DiskInode) -> bool {
         if new_size < disk_inode.size {
-            return;
+            return true;
         }
         let blocks_needed = disk_inode.blocks_num_needed(new_size);
         let mut v: Vec<u32> = Vec::new();
+        let mut fs = self.fs.lock();
         for _ in 0..blocks_needed {
-            v.push(fs.alloc_data());
+            match fs.alloc_data() {
+                Some(block_id) => v.push(block_id),
+                None => {
+                    for block_id in v {
+                        fs.dealloc_data(block_id);
+                    }
+                    return false;
+                }
+            }
         }
+        drop(fs);
         disk_inode.increase_size(new_size, v, &self.block_device);
+        true
     }
 
-    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
-        let mut fs = self.fs.lock();
-        let op = |root_inode: &mut DiskInode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
-            // has the file been created?
-            self.find_inode_id(name, root_inode)
+    /// Write a dirent into the first removed record large enough or append it,
+    /// return false if the directory can't grow.
+    fn add_dirent(&self, dirent: &DirEntry, disk_inode: &mut DiskInode) -> bool {
+        let len = self.dirent_format.record_len(dirent.name().len());
+        let free = self
+            .dirents(disk_inode)
+            .iter()
+            .find(|(_, free_len, old)| old.is_empty() && *free_len >= len)
+            .map(|(offset, free_len, _)| (*offset, *free_len));
+        let (offset, len) = match free {
+            Some(free) => free,
+            None => {
+                let offset = disk_inode.size as usize;
+                if !self.increase_size((offset + len) as u32, disk_inode) {
+                    return false;
+                }
+                (offset, len)
+            }
         };
-        if self.modify_disk_inode(op).is_some() {
+        self.write_dirent(offset, len, dirent, disk_inode);
+        true
+    }
+
+    /// Overwrite the record at `pos`, the offset and length `find_dirent` returned,
+    /// with `dirent`, whose name is the old one or empty to remove it.
+    fn set_dirent(&self, pos: (usize, usize), dirent: &DirEntry, now: u64) {
+        self.modify_disk_inode(|disk_inode| {
+            self.write_dirent(pos.0, pos.1, dirent, disk_inode);
+            disk_inode.touch(now, true);
+        });
+    }
+
+    /// Add a dirent `name` pointing to `inode` and count the new link,
+    /// return false if the file system is full.
+    /// Every dirent is a link, `.` and `..` included.
+    fn add_link(&self, name: &str, inode: &Inode) -> bool {
+        let now = self.locks.now();
+        let added = self.modify_disk_inode(|disk_inode| {
+            let added = self.add_dirent(&DirEntry::new(name, inode.inode_id), disk_inode);
+            if added {
+                disk_inode.touch(now, true);
+            }
+            added
+        });
+        if added {
+            inode.modify_disk_inode(|disk_inode| {
+                disk_inode.nlink += 1;
+                disk_inode.touch(now, false);
+            });
+        }
+        added
+    }
+
+    /// Drop a link to this inode. With the last one its blocks and the inode itself
+    /// are freed, or once the last `Inode` of it is dropped if it is still open.
+    fn drop_link(&self) {
+        let now = self.locks.now();
+        let nlink = self.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink -= 1;
+            disk_inode.touch(now, false);
+            disk_inode.nlink
+        });
+        if nlink == 0 && !self.locks.orphan_if_open(self.inode_id, &self.lock) {
+            self.free();
+        }
+    }
+
+    /// Free the blocks and the inode, within an operation.
+    fn free(&self) {
+        let _content = self.lock.write();
+        let data_blocks =
+            self.modify_disk_inode(|disk_inode| disk_inode.clear_size(&self.block_device));
+        let mut fs = self.fs.lock();
+        for data_block in data_blocks {
+            fs.dealloc_data(data_block);
+        }
+        fs.dealloc_inode(self.inode_id);
+    }
+
+    /// Begin an operation once the orphans no `Inode` refers to any more are freed,
+    /// each in an operation of its own. No lock may be held.
+    fn begin_op(&self) -> Operation {
+        while let Some(inode_id) = self.locks.take_reclaimable() {
+            let _op = self.locks.begin_op();
+            self.get_inode(inode_id).free();
+        }
+        self.locks.begin_op()
+    }
+
+    /// Write `.` and `..` into a new directory, return false if the file system is full.
+    pub(crate) fn init_dir(&self, parent: &Inode) -> bool {
+        self.add_link(".", self) && self.add_link("..", parent)
+    }
+
+    /// Return true if this directory only contains `.` and `..`.
+    fn is_empty_dir(&self) -> bool {
+        let disk_inode = self.disk_inode();
+        disk_inode.is_dir()
+            && self.dirents(&disk_inode).iter().all(|(_, _, dirent)| {
+                dirent.is_empty() || dirent.name() == "." || dirent.name() == ".."
+            })
+    }
+
+    /// Look `name` up in this directory, the namespace lock must be held.
+    /// Return the offset and length of its record and the inode.
+    fn lookup(&self, name: &str) -> Option<((usize, usize), Arc<Inode>)> {
+        let disk_inode = self.disk_inode();
+        if !disk_inode.is_dir() {
+            return None;
+        }
+        self.find_dirent(name, &disk_inode)
+            .map(|(pos, inode_id)| (pos, self.get_inode(inode_id)))
+    }
+
+    fn create_inode(&self, name: &str, type_: DiskInodeType) -> Option<Arc<Inode>> {
+        if !self.is_valid_name(name) {
+            return None;
+        }
+        let _op = self.begin_op();
+        let _namespace = self.locks.namespace.write();
+        // has the file been created?
+        if !self.is_dir() || self.lookup(name).is_some() {
             return None;
         }
         // create a new file
         // alloc a inode with an indirect block
-        let new_inode_id = fs.alloc_inode();
+        let new_inode_id = self.fs.lock().alloc_inode()?;
         // initialize inode
-        let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
-            .lock()
-            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
-            });
-        self.modify_disk_inode(|root_inode| {
-            // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
-        });
-
-        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all();
+        let inode = self.get_inode(new_inode_id);
+        let now = self.locks.now();
+        inode.modify_disk_inode(|new_inode| new_inode.initialize(type_, now));
+        let is_dir = type_ == DiskInodeType::Directory;
+        // the new inode is freed if any block is missing
+        if is_dir && !inode.init_dir(self) {
+            inode.free();
+            return None;
+        }
+        if !self.add_link(name, &inode) {
+            if is_dir {
+                // the `..` of the new directory
+                self.modify_disk_inode(|disk_inode| disk_inode.nlink -= 1);
+            }
+            inode.free();
+            return None;
+        }
         // return inode
-        Some(Arc::new(Self::new(
-            block_id,
-            block_offset,
-            self.fs.clone(),
-            self.block_device.clone(),
-        )))
-        // release efs lock automatically by compiler
+        Some(inode)
+        // end the operation automatically by compiler
     }
 
-    pub fn ls(&self) -> Vec<String> {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-            let mut v: Vec<String> = Vec::new();
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device,),
-                    DIRENT_SZ,
-                );
-                v.push(String::from(dirent.name()));
+    /// Create a regular file in this directory.
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+
+    /// Create a directory containing `.` and `..` in this directory.
+    pub fn mkdir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory)
+    }
+
+    /// Remove the empty directory `name`, its inode and blocks are freed.
+    pub fn rmdir(&self, name: &str) -> bool {
+        if name == "." || name == ".." {
+            return false;
+        }
+        let _op = self.begin_op();
+        let _namespace = self.locks.namespace.write();
+        let (pos, inode) = match self.lookup(name) {
+            Some(found) => found,
+            None => return false,
+        };
+        if !inode.is_empty_dir() {
+            return false;
+        }
+        self.set_dirent(pos, &DirEntry::empty(), self.locks.now());
+        // the `..` of the child
+        self.drop_link();
+        // the `.` of the child and its dirent here
+        inode.drop_link();
+        inode.drop_link();
+        true
+    }
+
+    /// Remove the dirent `name`, which must not be a directory.
+    pub fn unlink(&self, name: &str) -> bool {
+        let _op = self.begin_op();
+        let _namespace = self.locks.namespace.write();
+        let (pos, inode) = match self.lookup(name) {
+            Some(found) => found,
+            None => return false,
+        };
+        if inode.is_dir() {
+            return false;
+        }
+        self.set_dirent(pos, &DirEntry::empty(), self.locks.now());
+        inode.drop_link();
+        true
+    }
+
+    /// Add a hard link `name` in this directory to `inode`,
+    /// which must be a file on the same file system.
+    pub fn link(&self, name: &str, inode: &Inode) -> bool {
+        if !self.is_valid_name(name) || !Arc::ptr_eq(&self.fs, &inode.fs) {
+            return false;
+        }
+        let _op = self.begin_op();
+        let _namespace = self.locks.namespace.write();
+        if !self.is_dir() || inode.is_dir() || self.lookup(name).is_some() {
+            return false;
+        }
+        self.add_link(name, inode)
+    }
+
+    /// Return true if `self` is `inode` or lies below it.
+    fn is_under(&self, inode_id: u32) -> bool {
+        let mut current = self.get_inode(self.inode_id);
+        loop {
+            if current.inode_id == inode_id {
+                return true;
             }
-            v
-        })
+            if current.inode_id == 0 {
+                return false;
+            }
+            current = match current.lookup("..") {
+                Some((_, parent)) => parent,
+                None => return false,
+            };
+        }
+    }
+
+    /// Move the dirent `old_name` to `new_name` in `new_dir`. An existing
+    /// file at `new_name` is replaced if the moved inode is not a directory.
+    /// Return false if `new_dir` needs a block and the file system is full.
+    pub fn rename(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> bool {
+        if old_name == "." || old_name == ".." {
+            return false;
+        }
+        if !self.is_valid_name(new_name) || !Arc::ptr_eq(&self.fs, &new_dir.fs) {
+            return false;
+        }
+        let _op = self.begin_op();
+        let _namespace = self.locks.namespace.write();
+        let (old_pos, inode) = match self.lookup(old_name) {
+            Some(found) => found,
+            None => return false,
+        };
+        if !new_dir.is_dir() {
+            return false;
+        }
+        let is_dir = inode.is_dir();
+        // a directory cannot be moved into itself
+        if is_dir && new_dir.is_under(inode.inode_id) {
+            return false;
+        }
+        let now = self.locks.now();
+        if let Some((new_pos, old_inode)) = new_dir.lookup(new_name) {
+            if old_inode.inode_id == inode.inode_id {
+                return true;
+            }
+            if is_dir || old_inode.is_dir() {
+                return false;
+            }
+            // the dirent of the replaced file is reused
+            new_dir.set_dirent(new_pos, &DirEntry::new(new_name, inode.inode_id), now);
+            old_inode.drop_link();
+        } else {
+            // the link count of the moved inode does not change
+            let added = new_dir.modify_disk_inode(|disk_inode| {
+                let added =
+                    new_dir.add_dirent(&DirEntry::new(new_name, inode.inode_id), disk_inode);
+                if added {
+                    disk_inode.touch(now, true);
+                }
+                added
+            });
+            if !added {
+                return false;
+            }
+        }
+        self.set_dirent(old_pos, &DirEntry::empty(), now);
+        inode.modify_disk_inode(|disk_inode| disk_inode.touch(now, false));
+        if is_dir && self.inode_id != new_dir.inode_id {
+            // point `..` to the new parent in place
+            let (parent_pos, _) = inode.lookup("..").unwrap();
+            inode.set_dirent(parent_pos, &DirEntry::new("..", new_dir.inode_id), now);
+            self.drop_link();
+            new_dir.modify_disk_inode(|disk_inode| {
+                disk_inode.nlink += 1;
+                disk_inode.touch(now, false);
+            });
+        }
+        true
+    }
+
+    pub fn ls(&self) -> Vec<String> {
+        let _namespace = self.locks.namespace.read();
+        self.dirents(&self.disk_inode())
+            .iter()
+            .filter(|(_, _, dirent)| !dirent.is_empty())
+            .map(|(_, _, dirent)| String::from(dirent.name()))
+            .collect()
+    }
+
+    /// Read the live dirents from byte `offset` on, empty if `self` is not a directory.
+    pub fn read_dir(&self, offset: usize) -> Vec<DirEntryInfo> {
+        let _namespace = self.locks.namespace.read();
+        let disk_inode = self.disk_inode();
+        let dirents = if disk_inode.is_dir() {
+            self.dirents(&disk_inode)
+        } else {
+            Vec::new()
+        };
+        dirents
+            .iter()
+            .filter(|(pos, _, dirent)| *pos >= offset && !dirent.is_empty())
+            .map(|(pos, len, dirent)| {
+                let inode = self.get_inode(dirent.inode_number());
+                DirEntryInfo {
+                    name: String::from(dirent.name()),
+                    inode_id: dirent.inode_number(),
+                    is_dir: inode.is_dir(),
+                    next_offset: pos + len,
+                }
+            })
+            .collect()
     }
 
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
+        let _content = self.lock.read();
+        self.disk_inode().read_at(offset, buf, &self.block_device)
     }
 
+    /// Large writes grow the file in several transactions, so a crash may leave
+    /// part of `buf` written, and reads may see part of it.
+    /// Return the bytes written, fewer than `buf.len()` once the file system is full.
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
-        let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
-        });
-        block_cache_sync_all();
-        size
+        let now = self.locks.now();
+        let end = offset + buf.len();
+        let mut pos = offset;
+        loop {
+            let _op = self.begin_op();
+            let _content = self.lock.write();
+            let (size, grown) = self.modify_disk_inode(|disk_inode| {
+                let size = disk_inode.size as usize;
+                // short of a whole chunk, the blocks left may still take a block more
+                let grown = self.increase_size((size + WRITE_CHUNK_SZ).min(end) as u32, disk_inode)
+                    || self.increase_size(
+                        ((size / BLOCK_SZ + 1) * BLOCK_SZ).min(end) as u32,
+                        disk_inode,
+                    );
+                disk_inode.touch(now, true);
+                // write the part of `buf` the file covers by now
+                let chunk_end = (disk_inode.size as usize).min(end);
+                if pos < chunk_end {
+                    pos += disk_inode.write_at(
+                        pos,
+                        &buf[pos - offset..chunk_end - offset],
+                        &self.block_device,
+                    );
+                }
+                (disk_inode.size as usize, grown)
+            });
+            if (pos == end && size >= end) || !grown {
+                break;
+            }
+        }
+        pos - offset
+    }
+
+    /// Write back everything changed on the file system of this inode.
+    /// It waits for the operations running on other inodes.
+    pub fn sync_fs(&self) {
+        drop(self.begin_op());
+        self.locks.sync();
     }
 
     pub fn clear(&self) {
-        let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
+        let _op = self.begin_op();
+        let _content = self.lock.write();
+        let now = self.locks.now();
+        let data_blocks_dealloc = self.modify_disk_inode(|disk_inode| {
+            disk_inode.touch(now, true);
             let size = disk_inode.size;
             let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
             assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
-            for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
-            }
+            data_blocks_dealloc
         });
-        block_cache_sync_all();
+        let mut fs = self.fs.lock();
+        for data_block in data_blocks_dealloc.into_iter() {
+            fs.dealloc_data(data_block);
+        }
+    }
+}
+
+impl Drop for Inode {
+    fn drop(&mut self) {
+        // SAFETY: the lock is not used after this
+        let lock = unsafe { ManuallyDrop::take(&mut self.lock) };
+        self.locks.release_inode(self.inode_id, lock);
     }
 }
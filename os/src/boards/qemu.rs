@@ -13,28 +13,55 @@ pub type CharDeviceImpl = crate::drivers::chardev::NS16550a<VIRT_UART>;
 
 pub const VIRT_PLIC: usize = 0xC00_0000;
 pub const VIRT_UART: usize = 0x1000_0000;
+/// virtio-mmio slots, QEMU plugs devices in from the last one in command line order.
+/// The IRQ of a slot is its index plus one.
+const VIRTIO_MMIO: usize = 0x1000_1000;
+const VIRTIO_MMIO_SIZE: usize = 0x1000;
+const VIRTIO_MMIO_SLOTS: usize = 8;
 #[allow(unused)]
 pub const VIRTGPU_XRES: u32 = 1280;
 #[allow(unused)]
 pub const VIRTGPU_YRES: u32 = 800;
 
-use crate::drivers::block::BLOCK_DEVICE;
+use crate::drivers::block::{add_block_device, block_device_irqs, block_device_of_irq};
+use crate::drivers::bus::virtio::{virtio_device_id, VIRTIO_DEVICE_BLOCK};
 use crate::drivers::chardev::{CharDevice, UART};
 use crate::drivers::plic::{IntrTargetPriority, PLIC};
 use crate::drivers::{KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::hart::hart_id;
+use alloc::sync::Arc;
 
+/// IRQs of the keyboard, the mouse and the UART.
+const CHAR_DEVICE_IRQS: [usize; 3] = [5, 6, 10];
+
+/// Find the devices and set the priorities of their interrupts, once on the boot hart.
 pub fn device_init() {
+    let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
+    for intr_src_id in CHAR_DEVICE_IRQS {
+        plic.set_priority(intr_src_id, 1);
+    }
+    // block devices get their names in command line order
+    for slot in (0..VIRTIO_MMIO_SLOTS).rev() {
+        let addr = VIRTIO_MMIO + slot * VIRTIO_MMIO_SIZE;
+        if virtio_device_id(addr) == Some(VIRTIO_DEVICE_BLOCK) {
+            let intr_src_id = slot + 1;
+            add_block_device(intr_src_id, Arc::new(BlockDeviceImpl::new(addr)));
+            plic.set_priority(intr_src_id, 1);
+        }
+    }
+}
+
+/// Let device interrupts reach this hart, after `device_init`.
+pub fn hart_init() {
     use riscv::register::sie;
     let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let hart_id: usize = 0;
+    let hart_id = hart_id();
     let supervisor = IntrTargetPriority::Supervisor;
     let machine = IntrTargetPriority::Machine;
     plic.set_threshold(hart_id, supervisor, 0);
     plic.set_threshold(hart_id, machine, 1);
-    //irq nums: 5 keyboard, 6 mouse, 8 block, 10 uart
-    for intr_src_id in [5usize, 6, 8, 10] {
+    for intr_src_id in CHAR_DEVICE_IRQS.into_iter().chain(block_device_irqs()) {
         plic.enable(hart_id, supervisor, intr_src_id);
-        plic.set_priority(intr_src_id, 1);
     }
     unsafe {
         sie::set_sext();
@@ -43,13 +70,18 @@ pub fn device_init() {
 
 pub fn irq_handler() {
     let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
-    let intr_src_id = plic.claim(0, IntrTargetPriority::Supervisor);
+    let hart_id = hart_id();
+    let intr_src_id = plic.claim(hart_id, IntrTargetPriority::Supervisor);
     match intr_src_id {
+        // another hart claimed it first
+        0 => return,
         5 => KEYBOARD_DEVICE.handle_irq(),
         6 => MOUSE_DEVICE.handle_irq(),
-        8 => BLOCK_DEVICE.handle_irq(),
         10 => UART.handle_irq(),
-        _ => panic!("unsupported IRQ {}", intr_src_id),
+        _ => match block_device_of_irq(intr_src_id as usize) {
+            Some(block_device) => block_device.handle_irq(),
+            None => panic!("unsupported IRQ {}", intr_src_id),
+        },
     }
-    plic.complete(0, IntrTargetPriority::Supervisor, intr_src_id);
+    plic.complete(hart_id, IntrTargetPriority::Supervisor, intr_src_id);
 }
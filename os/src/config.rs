@@ -3,10 +3,20 @@
 pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x100_0000;
+/// Harts with an ID from this on are not started.
+pub const MAX_HARTS: usize = 8;
+/// Each hart boots on a stack of this size in `entry.asm`, which shifts the hart ID by 16.
+pub const BOOT_STACK_SIZE: usize = 4096 * 16;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
+/// Lowest address picked by mmap when the caller gives no hint.
+pub const MMAP_BASE: usize = 0x1_0000_0000;
+/// User stacks of all threads are placed upwards from here.
+pub const USER_STACK_BASE: usize = 0x30_0000_0000;
+/// mmap stays below the user stacks.
+pub const MMAP_END: usize = USER_STACK_BASE;
 
 pub use crate::board::{CLOCK_FREQ, MEMORY_END, MMIO};
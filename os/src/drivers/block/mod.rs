@@ -2,18 +2,52 @@ mod virtio_blk;
 
 pub use virtio_blk::VirtIOBlock;
 
-use crate::board::BlockDeviceImpl;
+use crate::sync::IntrSpinLock;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use easy_fs::BlockDevice;
 use lazy_static::*;
 
 lazy_static! {
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+    /// Block devices found by `board::device_init` and their IRQs, `vda` first.
+    static ref BLOCK_DEVICES: IntrSpinLock<Vec<(usize, Arc<dyn BlockDevice>)>> =
+        unsafe { IntrSpinLock::new(Vec::new()) };
+}
+
+/// Register a device, it is named after the devices registered before it.
+pub fn add_block_device(irq: usize, device: Arc<dyn BlockDevice>) {
+    BLOCK_DEVICES.exclusive_access().push((irq, device));
+}
+
+/// Return the `index`th device found, which is called `vda`, `vdb`... after it.
+pub fn block_device(index: usize) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .exclusive_access()
+        .get(index)
+        .map(|(_, device)| device.clone())
+}
+
+/// Return the IRQs of all devices.
+pub fn block_device_irqs() -> Vec<usize> {
+    BLOCK_DEVICES
+        .exclusive_access()
+        .iter()
+        .map(|(irq, _)| *irq)
+        .collect()
+}
+
+/// Return the device raising `irq`.
+pub fn block_device_of_irq(irq: usize) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES
+        .exclusive_access()
+        .iter()
+        .find(|(device_irq, _)| *device_irq == irq)
+        .map(|(_, device)| device.clone())
 }
 
 #[allow(unused)]
 pub fn block_device_test() {
-    let block_device = BLOCK_DEVICE.clone();
+    let block_device = block_device(0).unwrap();
     let mut write_buffer = [0u8; 512];
     let mut read_buffer = [0u8; 512];
     for i in 0..512 {
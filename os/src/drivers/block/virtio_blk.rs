@@ -1,16 +1,13 @@
 use super::BlockDevice;
 use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::sync::{Condvar, IntrSpinLock};
 use crate::task::schedule;
 use crate::DEV_NON_BLOCKING_ACCESS;
 use alloc::collections::BTreeMap;
 use virtio_drivers::{BlkResp, RespStatus, VirtIOBlk, VirtIOHeader};
 
-#[allow(unused)]
-const VIRTIO0: usize = 0x10008000;
-
 pub struct VirtIOBlock {
-    virtio_blk: UPIntrFreeCell<VirtIOBlk<'static, VirtioHal>>,
+    virtio_blk: IntrSpinLock<VirtIOBlk<'static, VirtioHal>>,
     condvars: BTreeMap<u16, Condvar>,
 }
 
@@ -67,10 +64,11 @@ impl BlockDevice for VirtIOBlock {
 }
 
 impl VirtIOBlock {
-    pub fn new() -> Self {
+    /// `addr` is the virtio-mmio slot of the device.
+    pub fn new(addr: usize) -> Self {
         let virtio_blk = unsafe {
-            UPIntrFreeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
+            IntrSpinLock::new(
+                VirtIOBlk::<VirtioHal>::new(&mut *(addr as *mut VirtIOHeader)).unwrap(),
             )
         };
         let mut condvars = BTreeMap::new();
@@ -2,14 +2,29 @@ use crate::mm::{
     frame_alloc_more, frame_dealloc, kernel_token, FrameTracker, PageTable, PhysAddr, PhysPageNum,
     StepByOne, VirtAddr,
 };
-use crate::sync::UPIntrFreeCell;
+use crate::sync::IntrSpinLock;
 use alloc::vec::Vec;
 use lazy_static::*;
 use virtio_drivers::Hal;
 
 lazy_static! {
-    static ref QUEUE_FRAMES: UPIntrFreeCell<Vec<FrameTracker>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref QUEUE_FRAMES: IntrSpinLock<Vec<FrameTracker>> =
+        unsafe { IntrSpinLock::new(Vec::new()) };
+}
+
+/// "virt" in little endian, at the start of every virtio-mmio slot.
+const VIRTIO_MMIO_MAGIC: u32 = 0x7472_6976;
+pub const VIRTIO_DEVICE_BLOCK: u32 = 2;
+
+/// Return the device ID of the virtio-mmio slot at `addr`, `None` if nothing is plugged in.
+pub fn virtio_device_id(addr: usize) -> Option<u32> {
+    let (magic, device_id) = unsafe {
+        (
+            (addr as *const u32).read_volatile(),
+            ((addr + 8) as *const u32).read_volatile(),
+        )
+    };
+    (magic == VIRTIO_MMIO_MAGIC && device_id != 0).then_some(device_id)
 }
 
 pub struct VirtioHal;
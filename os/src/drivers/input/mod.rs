@@ -1,5 +1,5 @@
 use crate::drivers::bus::virtio::VirtioHal;
-use crate::sync::{Condvar, UPIntrFreeCell};
+use crate::sync::{Condvar, IntrSpinLock};
 use crate::task::schedule;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
@@ -15,7 +15,7 @@ struct VirtIOInputInner {
 }
 
 struct VirtIOInputWrapper {
-    inner: UPIntrFreeCell<VirtIOInputInner>,
+    inner: IntrSpinLock<VirtIOInputInner>,
     condvar: Condvar,
 }
 
@@ -39,7 +39,7 @@ impl VirtIOInputWrapper {
             events: VecDeque::new(),
         };
         Self {
-            inner: unsafe { UPIntrFreeCell::new(inner) },
+            inner: unsafe { IntrSpinLock::new(inner) },
             condvar: Condvar::new(),
         }
     }
@@ -6,7 +6,6 @@ pub mod input;
 pub mod net;
 pub mod plic;
 
-pub use block::BLOCK_DEVICE;
 pub use bus::*;
 pub use gpu::*;
 pub use input::*;
@@ -1,7 +1,7 @@
 use core::any::Any;
 
 use crate::drivers::virtio::VirtioHal;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::IntrSpinLock;
 use alloc::sync::Arc;
 use lazy_static::*;
 use virtio_drivers::{VirtIOHeader, VirtIONet};
@@ -17,7 +17,7 @@ pub trait NetDevice: Send + Sync + Any {
     fn receive(&self, data: &mut [u8]) -> usize;
 }
 
-pub struct VirtIONetWrapper(UPIntrFreeCell<VirtIONet<'static, VirtioHal>>);
+pub struct VirtIONetWrapper(IntrSpinLock<VirtIONet<'static, VirtioHal>>);
 
 impl NetDevice for VirtIONetWrapper {
     fn transmit(&self, data: &[u8]) {
@@ -40,7 +40,7 @@ impl VirtIONetWrapper {
         unsafe {
             let virtio = VirtIONet::<VirtioHal>::new(&mut *(VIRTIO8 as *mut VirtIOHeader))
                 .expect("can't create net device by virtio");
-            VirtIONetWrapper(UPIntrFreeCell::new(virtio))
+            VirtIONetWrapper(IntrSpinLock::new(virtio))
         }
     }
 }
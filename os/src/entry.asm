@@ -1,12 +1,25 @@
     .section .text.entry
     .globl _start
 _start:
+    # a0 = hart ID, every hart boots on its own stack of BOOT_STACK_SIZE = 1 << 16
+    mv tp, a0
     la sp, boot_stack_top
+    slli t0, a0, 16
+    sub sp, sp, t0
     call rust_main
 
+    .globl _start_secondary
+_start_secondary:
+    mv tp, a0
+    la sp, boot_stack_top
+    slli t0, a0, 16
+    sub sp, sp, t0
+    call rust_main_secondary
+
     .section .bss.stack
     .globl boot_stack_lower_bound
 boot_stack_lower_bound:
-    .space 4096 * 16
+    # MAX_HARTS stacks of BOOT_STACK_SIZE
+    .space 4096 * 16 * 8
     .globl boot_stack_top
 boot_stack_top:
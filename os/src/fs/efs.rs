@@ -0,0 +1,149 @@
+use super::vfs::{DirEntry, FileSystem, VfsInode};
+use super::{Stat, StatMode};
+use crate::drivers::block::block_device;
+use crate::task::{current_task, suspend_current_and_run_next};
+use crate::timer::get_time_ms;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use easy_fs::{EasyFileSystem, Inode};
+use spin::Mutex;
+
+/// Let the threads in other operations run while waiting for them.
+fn park() {
+    // nothing else runs before the first thread
+    if current_task().is_some() {
+        suspend_current_and_run_next();
+    }
+}
+
+/// An easy-fs on a block device.
+pub struct EasyFs {
+    efs: Arc<Mutex<EasyFileSystem>>,
+    root: Arc<EfsInode>,
+}
+
+impl EasyFs {
+    /// Return `None` if there is no such device or it holds no easy-fs.
+    pub fn open(dev: usize) -> Option<Self> {
+        let block_device = block_device(dev)?;
+        if !EasyFileSystem::probe(&block_device) {
+            return None;
+        }
+        let efs = EasyFileSystem::open(block_device);
+        efs.lock().set_clock(|| get_time_ms() as u64);
+        efs.lock().set_park(park);
+        let root = Arc::new(EfsInode {
+            inode: Arc::new(EasyFileSystem::root_inode(&efs)),
+            dev,
+        });
+        Some(Self { efs, root })
+    }
+}
+
+impl FileSystem for EasyFs {
+    fn root(&self) -> Arc<dyn VfsInode> {
+        self.root.clone()
+    }
+    fn sync(&self) {
+        self.root.inode.sync_fs();
+    }
+    fn busy(&self) -> bool {
+        // held by this and the root inode when nothing else is open
+        Arc::strong_count(&self.efs) > 2
+    }
+}
+
+struct EfsInode {
+    inode: Arc<Inode>,
+    /// Index of the block device, reported by `stat`.
+    dev: usize,
+}
+
+impl EfsInode {
+    fn of(inode: &dyn VfsInode) -> Option<&Self> {
+        inode.as_any().downcast_ref()
+    }
+    fn wrap(&self, inode: Arc<Inode>) -> Arc<dyn VfsInode> {
+        Arc::new(Self {
+            inode,
+            dev: self.dev,
+        })
+    }
+}
+
+impl VfsInode for EfsInode {
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.inode.find(name).map(|inode| self.wrap(inode))
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.inode.read_at(offset, buf)
+    }
+    fn readdir(&self, offset: usize) -> Vec<DirEntry> {
+        self.inode
+            .read_dir(offset)
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: entry.name,
+                ino: entry.inode_id as u64,
+                is_dir: entry.is_dir,
+                next_offset: entry.next_offset,
+            })
+            .collect()
+    }
+    fn stat(&self) -> Stat {
+        let metadata = self.inode.metadata();
+        let file_type = if metadata.is_dir {
+            StatMode::DIR
+        } else {
+            StatMode::FILE
+        };
+        Stat {
+            dev: self.dev as u64,
+            ino: metadata.inode_id as u64,
+            mode: file_type.bits() | metadata.mode as u32,
+            nlink: metadata.nlink as u32,
+            size: metadata.size as u64,
+            blocks: metadata.blocks as u64,
+            mtime: metadata.mtime,
+            ctime: metadata.ctime,
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        self.inode.write_at(offset, buf)
+    }
+    fn truncate(&self) -> bool {
+        if self.inode.is_dir() {
+            return false;
+        }
+        self.inode.clear();
+        true
+    }
+    fn create(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.inode.create(name).map(|inode| self.wrap(inode))
+    }
+    fn mkdir(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.inode.mkdir(name).map(|inode| self.wrap(inode))
+    }
+    fn unlink(&self, name: &str) -> bool {
+        self.inode.unlink(name)
+    }
+    fn rmdir(&self, name: &str) -> bool {
+        self.inode.rmdir(name)
+    }
+    fn link(&self, name: &str, inode: &dyn VfsInode) -> bool {
+        // easy-fs checks the file is on the same image
+        EfsInode::of(inode).map_or(false, |inode| self.inode.link(name, &inode.inode))
+    }
+    fn rename(&self, old_name: &str, new_dir: &dyn VfsInode, new_name: &str) -> bool {
+        EfsInode::of(new_dir).map_or(false, |new_dir| {
+            self.inode.rename(old_name, &new_dir.inode, new_name)
+        })
+    }
+    fn is_dir(&self) -> bool {
+        self.inode.is_dir()
+    }
+}
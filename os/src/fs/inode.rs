@@ -1,59 +1,57 @@
-use super::File;
-use crate::drivers::BLOCK_DEVICE;
+use super::mount::{has_mount_under, mount_root};
+use super::path::split_path;
+use super::vfs::VfsInode;
+use super::{File, SeekFrom, Stat, DIRENT_NAME_OFFSET, DT_DIR, DT_REG};
 use crate::mm::UserBuffer;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::IntrSpinLock;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
-use lazy_static::*;
 
 pub struct OSInode {
     readable: bool,
     writable: bool,
-    inner: UPIntrFreeCell<OSInodeInner>,
-}
-
-pub struct OSInodeInner {
-    offset: usize,
-    inode: Arc<Inode>,
+    /// Every write goes to the end of the file.
+    append: bool,
+    /// Only guards the offset, inode I/O may sleep and runs without it.
+    offset: IntrSpinLock<usize>,
+    inode: Arc<dyn VfsInode>,
 }
 
 impl OSInode {
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    pub fn new(readable: bool, writable: bool, append: bool, inode: Arc<dyn VfsInode>) -> Self {
         Self {
             readable,
             writable,
-            inner: unsafe { UPIntrFreeCell::new(OSInodeInner { offset: 0, inode }) },
+            append,
+            offset: unsafe { IntrSpinLock::new(0) },
+            inode,
         }
     }
     pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
+        let mut offset = *self.offset.exclusive_access();
         let mut buffer = [0u8; 512];
         let mut v: Vec<u8> = Vec::new();
         loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            let len = self.inode.read_at(offset, &mut buffer);
             if len == 0 {
                 break;
             }
-            inner.offset += len;
+            offset += len;
             v.extend_from_slice(&buffer[..len]);
         }
+        *self.offset.exclusive_access() = offset;
         v
     }
 }
 
-lazy_static! {
-    pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
-    };
-}
-
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
-        println!("{}", app);
+    for app in find_inode("/").unwrap().readdir(0) {
+        if app.is_dir {
+            continue;
+        }
+        println!("{}", app.name);
     }
     println!("**************/")
 }
@@ -65,6 +63,7 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const APPEND = 1 << 11;
     }
 }
 
@@ -82,26 +81,87 @@ impl OpenFlags {
     }
 }
 
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// Walk `path` from the root of the file system it is on, `path` should be normalized.
+fn find_inode(path: &str) -> Option<Arc<dyn VfsInode>> {
+    let (root, rest) = mount_root(path);
+    rest.split('/')
+        .filter(|name| !name.is_empty())
+        .try_fold(root, |inode, name| inode.lookup(name))
+}
+
+/// `path` should be normalized, directories can only be opened read-only.
+pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
-            // clear size
-            inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
-        } else {
-            // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+    let inode = if let Some(inode) = find_inode(path) {
+        if inode.is_dir() && (writable || flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC)) {
+            return None;
         }
+        if flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC) && !inode.truncate() {
+            return None;
+        }
+        inode
+    } else if flags.contains(OpenFlags::CREATE) {
+        // create file
+        let (parent, name) = split_path(path);
+        find_inode(parent)?.create(name)?
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
-            }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+        return None;
+    };
+    let append = flags.contains(OpenFlags::APPEND);
+    Some(Arc::new(OSInode::new(readable, writable, append, inode)))
+}
+
+/// Create a directory at the normalized `path`.
+pub fn make_dir(path: &str) -> bool {
+    let (parent, name) = split_path(path);
+    find_inode(parent)
+        .and_then(|parent| parent.mkdir(name))
+        .is_some()
+}
+
+pub fn stat_file(path: &str) -> Option<Stat> {
+    find_inode(path).map(|inode| inode.stat())
+}
+
+pub fn dir_exists(path: &str) -> bool {
+    find_inode(path).map_or(false, |inode| inode.is_dir())
+}
+
+/// Remove the file at the normalized `path`, or the empty directory if `remove_dir`.
+/// Files still open keep their inode, which is freed once the last link is gone and they are closed.
+/// Mount points can't be removed.
+pub fn unlink_file(path: &str, remove_dir: bool) -> bool {
+    if has_mount_under(path) {
+        return false;
+    }
+    let (parent, name) = split_path(path);
+    match find_inode(parent) {
+        Some(parent) if remove_dir => parent.rmdir(name),
+        Some(parent) => parent.unlink(name),
+        None => false,
+    }
+}
+
+pub fn link_file(old_path: &str, new_path: &str) -> bool {
+    let (new_parent, new_name) = split_path(new_path);
+    match (find_inode(old_path), find_inode(new_parent)) {
+        (Some(inode), Some(new_parent)) => new_parent.link(new_name, inode.as_ref()),
+        _ => false,
+    }
+}
+
+/// Both paths must be on the same file system and have no mount point below them.
+pub fn rename_file(old_path: &str, new_path: &str) -> bool {
+    if has_mount_under(old_path) || has_mount_under(new_path) {
+        return false;
+    }
+    let (old_parent, old_name) = split_path(old_path);
+    let (new_parent, new_name) = split_path(new_path);
+    match (find_inode(old_parent), find_inode(new_parent)) {
+        (Some(old_parent), Some(new_parent)) => {
+            old_parent.rename(old_name, new_parent.as_ref(), new_name)
+        }
+        _ => false,
     }
 }
 
@@ -113,27 +173,88 @@ impl File for OSInode {
         self.writable
     }
     fn read(&self, mut buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
+        let mut offset = *self.offset.exclusive_access();
         let mut total_read_size = 0usize;
         for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
+            let read_size = self.inode.read_at(offset, *slice);
             if read_size == 0 {
                 break;
             }
-            inner.offset += read_size;
+            offset += read_size;
             total_read_size += read_size;
         }
+        *self.offset.exclusive_access() = offset;
         total_read_size
     }
     fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access();
+        let mut offset = if self.append {
+            self.inode.stat().size as usize
+        } else {
+            *self.offset.exclusive_access()
+        };
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
+            let write_size = self.inode.write_at(offset, *slice);
+            offset += write_size;
             total_write_size += write_size;
+            // the file system is full
+            if write_size < slice.len() {
+                break;
+            }
         }
+        *self.offset.exclusive_access() = offset;
         total_write_size
     }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Option<usize> {
+        Some(self.inode.read_at(offset, buf))
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Option<usize> {
+        Some(self.inode.write_at(offset, buf))
+    }
+    fn seek(&self, pos: SeekFrom) -> Option<usize> {
+        // the size is read before locking, stat may sleep
+        let end = match pos {
+            SeekFrom::End(_) => self.inode.stat().size as usize,
+            _ => 0,
+        };
+        let mut cur = self.offset.exclusive_access();
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => cur.checked_add_signed(delta),
+            SeekFrom::End(delta) => end.checked_add_signed(delta),
+        }?;
+        *cur = offset;
+        Some(offset)
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(self.inode.stat())
+    }
+    fn read_dirents(&self, buf: &mut [u8]) -> Option<usize> {
+        if !self.inode.is_dir() {
+            return None;
+        }
+        let mut offset = *self.offset.exclusive_access();
+        let mut pos = 0usize;
+        for entry in self.inode.readdir(offset) {
+            let name_end = DIRENT_NAME_OFFSET + entry.name.len();
+            let reclen = (name_end + 1).next_multiple_of(8);
+            if pos + reclen > buf.len() {
+                if pos == 0 {
+                    return None;
+                }
+                break;
+            }
+            let record = &mut buf[pos..pos + reclen];
+            record.fill(0);
+            record[0..8].copy_from_slice(&entry.ino.to_le_bytes());
+            record[8..16].copy_from_slice(&(entry.next_offset as u64).to_le_bytes());
+            record[16..18].copy_from_slice(&(reclen as u16).to_le_bytes());
+            record[18] = if entry.is_dir { DT_DIR } else { DT_REG };
+            record[DIRENT_NAME_OFFSET..name_end].copy_from_slice(entry.name.as_bytes());
+            offset = entry.next_offset;
+            pos += reclen;
+        }
+        *self.offset.exclusive_access() = offset;
+        Some(pos)
+    }
 }
@@ -1,6 +1,11 @@
+mod efs;
 mod inode;
+mod mount;
+mod path;
 mod pipe;
 mod stdio;
+mod tmpfs;
+mod vfs;
 
 use crate::mm::UserBuffer;
 
@@ -9,8 +14,82 @@ pub trait File: Send + Sync {
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// Read from `offset` into a kernel buffer without moving the file offset.
+    /// Return `None` if the file is not seekable.
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Option<usize> {
+        None
+    }
+    /// Write a kernel buffer at `offset` without moving the file offset.
+    /// Return `None` if the file is not seekable.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Option<usize> {
+        None
+    }
+    /// Move the file offset and return the new one.
+    /// Return `None` if the file is not seekable or the offset would be negative.
+    fn seek(&self, _pos: SeekFrom) -> Option<usize> {
+        None
+    }
+    /// Return `None` if the file is not backed by an inode.
+    fn stat(&self) -> Option<Stat> {
+        None
+    }
+    /// Fill `buf` with dirent records from the file offset on and move the offset past them.
+    /// Return `None` if the file is not a directory or `buf` can't hold the next record.
+    fn read_dirents(&self, _buf: &mut [u8]) -> Option<usize> {
+        None
+    }
 }
 
-pub use inode::{list_apps, open_file, OpenFlags};
+/// Where `File::seek` counts the offset from.
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
+
+/// File status returned by `sys_fstat` and `sys_stat`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+    /// ID of the device containing the file
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// file type and permission bits
+    pub mode: u32,
+    /// number of hard links
+    pub nlink: u32,
+    pub size: u64,
+    /// number of 512B blocks in use
+    pub blocks: u64,
+    /// last modification time in ms
+    pub mtime: u64,
+    /// last status change time in ms
+    pub ctime: u64,
+}
+
+/// A record written by `sys_getdents64` is laid out as Linux `linux_dirent64`,
+/// `ino: u64`, `off: u64` of the next record, `reclen: u16` and `type: u8`
+/// without padding, then the name at this offset with a trailing `\0`,
+/// the record padded to 8 bytes.
+pub const DIRENT_NAME_OFFSET: usize = 19;
+
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+
+bitflags! {
+    /// File type bits of `Stat::mode`.
+    pub struct StatMode: u32 {
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+    }
+}
+
+pub use inode::{
+    dir_exists, link_file, list_apps, make_dir, open_file, rename_file, stat_file, unlink_file,
+    OpenFlags,
+};
+pub use mount::{mount, sync_all, umount};
+pub use path::normalize_path;
 pub use pipe::make_pipe;
 pub use stdio::{Stdin, Stdout};
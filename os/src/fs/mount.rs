@@ -0,0 +1,152 @@
+use super::dir_exists;
+use super::efs::EasyFs;
+use super::tmpfs::TmpFs;
+use super::vfs::{FileSystem, VfsInode};
+use crate::sync::IntrSpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// A file system in the mount table.
+struct Mount {
+    /// Normalized path of the mount point.
+    path: String,
+    /// Index of the block device, `None` for a tmpfs.
+    dev: Option<usize>,
+    fs: Arc<dyn FileSystem>,
+    root: Arc<dyn VfsInode>,
+}
+
+impl Mount {
+    /// Return `None` if there is no such device or it holds no easy-fs.
+    fn new(path: &str, dev: Option<usize>) -> Option<Self> {
+        let fs: Arc<dyn FileSystem> = match dev {
+            Some(dev) => Arc::new(EasyFs::open(dev)?),
+            None => Arc::new(TmpFs::new()),
+        };
+        let root = fs.root();
+        Some(Self {
+            path: String::from(path),
+            dev,
+            fs,
+            root,
+        })
+    }
+}
+
+lazy_static! {
+    /// The root file system is on `vda` and a tmpfs is on `/tmp`.
+    /// The table is never borrowed during disk I/O.
+    static ref MOUNTS: IntrSpinLock<Vec<Mount>> = {
+        let root = Mount::new("/", Some(0)).expect("no file system on the root device");
+        let tmp = root.root.lookup("tmp").or_else(|| root.root.mkdir("tmp"));
+        let mut mounts = vec![root];
+        if tmp.map_or(false, |tmp| tmp.is_dir()) {
+            mounts.push(Mount::new("/tmp", None).unwrap());
+        }
+        unsafe { IntrSpinLock::new(mounts) }
+    };
+}
+
+/// Return true if the normalized `path` is `mount_point` or lies below it.
+fn is_under(path: &str, mount_point: &str) -> bool {
+    mount_point == "/"
+        || path
+            .strip_prefix(mount_point)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Return the root of the file system the normalized `path` is on
+/// and the rest of `path` from there.
+pub fn mount_root(path: &str) -> (Arc<dyn VfsInode>, &str) {
+    let mounts = MOUNTS.exclusive_access();
+    let mount = mounts
+        .iter()
+        .filter(|mount| is_under(path, &mount.path))
+        .max_by_key(|mount| mount.path.len())
+        .unwrap();
+    (mount.root.clone(), &path[mount.path.len()..])
+}
+
+/// Return true if a file system is mounted at the normalized `path` or below it.
+pub fn has_mount_under(path: &str) -> bool {
+    MOUNTS
+        .exclusive_access()
+        .iter()
+        .any(|mount| is_under(&mount.path, path))
+}
+
+/// `vda` is the first block device, `vdb` the second and so on.
+fn device_index(name: &str) -> Option<usize> {
+    match name.as_bytes() {
+        [b'v', b'd', letter @ b'a'..=b'z'] => Some((letter - b'a') as usize),
+        _ => None,
+    }
+}
+
+/// Mount the device called `source`, or a new tmpfs if it is `tmpfs`, on the directory
+/// at the normalized `target`. A device is mounted at most once and a directory holds
+/// at most one file system.
+pub fn mount(source: &str, target: &str) -> bool {
+    let dev = match source {
+        "tmpfs" => None,
+        _ => match device_index(source) {
+            Some(dev) => Some(dev),
+            None => return false,
+        },
+    };
+    let is_free = |mounts: &[Mount]| {
+        !mounts
+            .iter()
+            .any(|mount| (dev.is_some() && mount.dev == dev) || mount.path == target)
+    };
+    if !is_free(&MOUNTS.exclusive_access()) || !dir_exists(target) {
+        return false;
+    }
+    // the superblock is read with the table released
+    let new_mount = match Mount::new(target, dev) {
+        Some(new_mount) => new_mount,
+        None => return false,
+    };
+    let mut mounts = MOUNTS.exclusive_access();
+    if !is_free(&mounts) {
+        return false;
+    }
+    mounts.push(new_mount);
+    true
+}
+
+/// Write back and unmount the file system at the normalized `target`. Fail if it is
+/// the root, a file on it is open or another file system is mounted below it.
+pub fn umount(target: &str) -> bool {
+    let mut mounts = MOUNTS.exclusive_access();
+    let index = match mounts.iter().position(|mount| mount.path == target) {
+        Some(index) if target != "/" => index,
+        _ => return false,
+    };
+    if mounts[index].fs.busy()
+        || mounts
+            .iter()
+            .any(|mount| mount.path != target && is_under(&mount.path, target))
+    {
+        return false;
+    }
+    let mount = mounts.remove(index);
+    drop(mounts);
+    mount.fs.sync();
+    true
+}
+
+/// Write back every file system mounted.
+pub fn sync_all() {
+    let mounted: Vec<Arc<dyn FileSystem>> = MOUNTS
+        .exclusive_access()
+        .iter()
+        .map(|mount| mount.fs.clone())
+        .collect();
+    for fs in mounted {
+        fs.sync();
+    }
+}
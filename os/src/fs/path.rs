@@ -0,0 +1,26 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Turn `path` into an absolute path without `.`, `..` or empty names,
+/// a relative `path` starts from `cwd`, which must be absolute.
+pub fn normalize_path(cwd: &str, path: &str) -> String {
+    let base = if path.starts_with('/') { "" } else { cwd };
+    let mut names: Vec<&str> = Vec::new();
+    for name in base.split('/').chain(path.split('/')) {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            _ => names.push(name),
+        }
+    }
+    let mut abs_path = String::from("/");
+    abs_path.push_str(&names.join("/"));
+    abs_path
+}
+
+/// Split a normalized path into its parent directory and last name.
+pub fn split_path(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
@@ -0,0 +1,453 @@
+use super::vfs::{DirEntry, FileSystem, VfsInode};
+use super::{Stat, StatMode};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, FrameTracker};
+use crate::sync::IntrSpinLock;
+use crate::timer::get_time_ms;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Device IDs of tmpfs start here, above those of block devices.
+const FIRST_DEV: usize = 256;
+
+static NEXT_DEV: AtomicUsize = AtomicUsize::new(FIRST_DEV);
+
+/// Files grow at most this large, so that one file can't take all the frames.
+const MAX_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+/// A file system in memory, the content of files is kept in frames
+/// of the frame allocator and freed with the last link and handle.
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Self {
+        let shared = Arc::new(TmpFsShared {
+            dev: NEXT_DEV.fetch_add(1, Ordering::Relaxed),
+            next_ino: AtomicU64::new(0),
+        });
+        Self {
+            root: TmpInode::new(&shared, true, Weak::new()),
+        }
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn root(&self) -> Arc<dyn VfsInode> {
+        self.root.clone()
+    }
+    /// Files unlinked while open don't count, their memory goes with the last close anyway.
+    fn busy(&self) -> bool {
+        // held by this and the mount table
+        Arc::strong_count(&self.root) > 2 || self.root.in_use_below()
+    }
+}
+
+struct TmpFsShared {
+    dev: usize,
+    next_ino: AtomicU64,
+}
+
+struct TmpInode {
+    ino: u64,
+    is_dir: bool,
+    fs: Arc<TmpFsShared>,
+    /// Lets `lookup` return this inode for `.`.
+    this: Weak<TmpInode>,
+    inner: IntrSpinLock<TmpInodeInner>,
+}
+
+struct TmpInodeInner {
+    /// Dirents naming this inode, only a file has more than one.
+    links: u32,
+    mtime: u64,
+    ctime: u64,
+    content: Content,
+}
+
+impl TmpInodeInner {
+    /// The dirents of a directory, `None` for a file.
+    fn entries(&mut self) -> Option<&mut Vec<(String, Arc<TmpInode>)>> {
+        match &mut self.content {
+            Content::Dir { entries, .. } => Some(entries),
+            Content::File { .. } => None,
+        }
+    }
+
+    fn touch(&mut self) {
+        self.mtime = get_time_ms() as u64;
+        self.ctime = self.mtime;
+    }
+}
+
+enum Content {
+    File {
+        pages: Vec<FrameTracker>,
+        size: usize,
+    },
+    Dir {
+        /// Empty for the root, whose `..` is itself.
+        parent: Weak<TmpInode>,
+        entries: Vec<(String, Arc<TmpInode>)>,
+    },
+}
+
+impl TmpInode {
+    fn new(fs: &Arc<TmpFsShared>, is_dir: bool, parent: Weak<TmpInode>) -> Arc<Self> {
+        let now = get_time_ms() as u64;
+        let content = if is_dir {
+            Content::Dir {
+                parent,
+                entries: Vec::new(),
+            }
+        } else {
+            Content::File {
+                pages: Vec::new(),
+                size: 0,
+            }
+        };
+        Arc::new_cyclic(|this| Self {
+            ino: fs.next_ino.fetch_add(1, Ordering::Relaxed),
+            is_dir,
+            fs: Arc::clone(fs),
+            this: this.clone(),
+            inner: unsafe {
+                IntrSpinLock::new(TmpInodeInner {
+                    links: 1,
+                    mtime: now,
+                    ctime: now,
+                    content,
+                })
+            },
+        })
+    }
+
+    fn of(inode: &dyn VfsInode) -> Option<&Self> {
+        inode.as_any().downcast_ref()
+    }
+
+    fn parent(&self) -> Option<Arc<TmpInode>> {
+        match &self.inner.exclusive_access().content {
+            Content::Dir { parent, .. } => parent.upgrade(),
+            Content::File { .. } => None,
+        }
+    }
+
+    /// Return the inode `name` names in this directory, `.` and `..` excluded.
+    fn entry(&self, name: &str) -> Option<Arc<TmpInode>> {
+        match &self.inner.exclusive_access().content {
+            Content::Dir { entries, .. } => entries
+                .iter()
+                .find(|(entry_name, _)| entry_name == name)
+                .map(|(_, inode)| inode.clone()),
+            Content::File { .. } => None,
+        }
+    }
+
+    /// Add the dirent `name` to this directory, which must not have it yet.
+    fn add_entry(&self, name: &str, inode: Arc<TmpInode>) {
+        let mut inner = self.inner.exclusive_access();
+        if let Some(entries) = inner.entries() {
+            entries.push((String::from(name), inode));
+        }
+        inner.touch();
+    }
+
+    /// Remove the dirent `name` from this directory and return the inode it named.
+    fn remove_entry(&self, name: &str) -> Option<Arc<TmpInode>> {
+        let mut inner = self.inner.exclusive_access();
+        let entries = inner.entries()?;
+        let index = entries
+            .iter()
+            .position(|(entry_name, _)| entry_name == name)?;
+        let inode = entries.remove(index).1;
+        inner.touch();
+        Some(inode)
+    }
+
+    /// Return true if `self` is `inode` or lies below it.
+    fn is_under(&self, inode: &TmpInode) -> bool {
+        let mut current = self.this.upgrade();
+        while let Some(dir) = current {
+            if dir.ino == inode.ino {
+                return true;
+            }
+            current = dir.parent();
+        }
+        false
+    }
+
+    /// Return true if an inode below this directory is held by more than its dirents.
+    fn in_use_below(&self) -> bool {
+        match &self.inner.exclusive_access().content {
+            Content::Dir { entries, .. } => entries.iter().any(|(_, inode)| {
+                let links = inode.inner.exclusive_access().links;
+                Arc::strong_count(inode) > links as usize || inode.in_use_below()
+            }),
+            Content::File { .. } => false,
+        }
+    }
+
+    fn is_valid_name(name: &str) -> bool {
+        !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+    }
+
+    /// The name is looked up and added under the same guard, so only one of
+    /// two racing calls makes it.
+    fn make(&self, name: &str, is_dir: bool) -> Option<Arc<dyn VfsInode>> {
+        if !Self::is_valid_name(name) {
+            return None;
+        }
+        let mut inner = self.inner.exclusive_access();
+        let entries = inner.entries()?;
+        if entries.iter().any(|(entry_name, _)| entry_name == name) {
+            return None;
+        }
+        let inode = TmpInode::new(&self.fs, is_dir, self.this.clone());
+        entries.push((String::from(name), inode.clone()));
+        inner.touch();
+        Some(inode)
+    }
+}
+
+impl VfsInode for TmpInode {
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode = match name {
+            "." if self.is_dir => self.this.upgrade(),
+            ".." if self.is_dir => self.parent().or_else(|| self.this.upgrade()),
+            _ => self.entry(name),
+        };
+        inode.map(|inode| inode as Arc<dyn VfsInode>)
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let inner = self.inner.exclusive_access();
+        let (pages, size) = match &inner.content {
+            Content::File { pages, size } => (pages, *size),
+            Content::Dir { .. } => return 0,
+        };
+        let end = (offset + buf.len()).min(size);
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            buf[pos - offset..pos - offset + len].copy_from_slice(
+                &pages[pos / PAGE_SIZE].ppn.get_bytes_array()[page_offset..page_offset + len],
+            );
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+    /// Directory offsets count entries, `.` and `..` come first.
+    fn readdir(&self, offset: usize) -> Vec<DirEntry> {
+        let this = match self.this.upgrade() {
+            Some(this) if self.is_dir => this,
+            _ => return Vec::new(),
+        };
+        let parent = self.parent().unwrap_or_else(|| this.clone());
+        let mut dirents = Vec::from([(String::from("."), this), (String::from(".."), parent)]);
+        if let Content::Dir { entries, .. } = &self.inner.exclusive_access().content {
+            dirents.extend(entries.iter().cloned());
+        }
+        dirents
+            .into_iter()
+            .enumerate()
+            .skip(offset)
+            .map(|(i, (name, inode))| DirEntry {
+                name,
+                ino: inode.ino,
+                is_dir: inode.is_dir,
+                next_offset: i + 1,
+            })
+            .collect()
+    }
+    fn stat(&self) -> Stat {
+        let inner = self.inner.exclusive_access();
+        let (mode, nlink, size, pages) = match &inner.content {
+            Content::File { pages, size } => (StatMode::FILE, inner.links, *size, pages.len()),
+            Content::Dir { entries, .. } => {
+                let subdirs = entries.iter().filter(|(_, inode)| inode.is_dir).count();
+                (StatMode::DIR, 2 + subdirs as u32, 0, 0)
+            }
+        };
+        Stat {
+            dev: self.fs.dev as u64,
+            ino: self.ino,
+            mode: mode.bits() | 0o777,
+            nlink,
+            size: size as u64,
+            blocks: (pages * PAGE_SIZE / 512) as u64,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    /// Write as much of `buf` as there are frames for, up to `MAX_FILE_SIZE`.
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let (pages, size) = match &mut inner.content {
+            Content::File { pages, size } => (pages, size),
+            Content::Dir { .. } => return 0,
+        };
+        let end = offset.saturating_add(buf.len()).min(MAX_FILE_SIZE);
+        let old_pages = pages.len();
+        while pages.len() * PAGE_SIZE < end {
+            match frame_alloc() {
+                Some(frame) => pages.push(frame),
+                None => break,
+            }
+        }
+        let end = end.min(pages.len() * PAGE_SIZE);
+        if end <= offset {
+            // nothing is written, the frames of the hole go back
+            pages.truncate(old_pages);
+            return 0;
+        }
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            pages[pos / PAGE_SIZE].ppn.get_bytes_array()[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        *size = (*size).max(end);
+        inner.touch();
+        end - offset
+    }
+    fn truncate(&self) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match &mut inner.content {
+            Content::File { pages, size } => {
+                pages.clear();
+                *size = 0;
+            }
+            Content::Dir { .. } => return false,
+        }
+        inner.touch();
+        true
+    }
+    fn create(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.make(name, false)
+    }
+    fn mkdir(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        self.make(name, true)
+    }
+    /// The dirent is found and removed under the guard of this directory,
+    /// so each link is dropped once.
+    fn unlink(&self, name: &str) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let entries = match inner.entries() {
+            Some(entries) => entries,
+            None => return false,
+        };
+        let index = match entries
+            .iter()
+            .position(|(entry_name, inode)| entry_name == name && !inode.is_dir)
+        {
+            Some(index) => index,
+            None => return false,
+        };
+        let inode = entries.remove(index).1;
+        inner.touch();
+        let mut inode_inner = inode.inner.exclusive_access();
+        inode_inner.links -= 1;
+        inode_inner.ctime = inner.ctime;
+        true
+    }
+    /// The directory stays locked while it is checked to be empty and removed.
+    fn rmdir(&self, name: &str) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        let entries = match inner.entries() {
+            Some(entries) => entries,
+            None => return false,
+        };
+        let index = match entries
+            .iter()
+            .position(|(entry_name, _)| entry_name == name)
+        {
+            Some(index) => index,
+            None => return false,
+        };
+        let dir = entries[index].1.clone();
+        let mut dir_inner = dir.inner.exclusive_access();
+        if !dir_inner
+            .entries()
+            .map_or(false, |entries| entries.is_empty())
+        {
+            return false;
+        }
+        entries.remove(index);
+        inner.touch();
+        true
+    }
+    fn link(&self, name: &str, inode: &dyn VfsInode) -> bool {
+        let inode = match Self::of(inode) {
+            Some(inode) if Arc::ptr_eq(&self.fs, &inode.fs) && !inode.is_dir => inode,
+            _ => return false,
+        };
+        if !Self::is_valid_name(name) {
+            return false;
+        }
+        let mut inner = self.inner.exclusive_access();
+        let entries = match inner.entries() {
+            Some(entries) => entries,
+            None => return false,
+        };
+        if entries.iter().any(|(entry_name, _)| entry_name == name) {
+            return false;
+        }
+        entries.push((String::from(name), inode.this.upgrade().unwrap()));
+        inner.touch();
+        let mut inode_inner = inode.inner.exclusive_access();
+        inode_inner.links += 1;
+        inode_inner.ctime = inner.ctime;
+        true
+    }
+    /// An existing file at `new_name` is replaced if the moved inode is not a directory.
+    fn rename(&self, old_name: &str, new_dir: &dyn VfsInode, new_name: &str) -> bool {
+        let new_dir = match Self::of(new_dir) {
+            Some(new_dir) if Arc::ptr_eq(&self.fs, &new_dir.fs) && new_dir.is_dir => new_dir,
+            _ => return false,
+        };
+        if !Self::is_valid_name(old_name) || !Self::is_valid_name(new_name) {
+            return false;
+        }
+        let inode = match self.entry(old_name) {
+            Some(inode) => inode,
+            None => return false,
+        };
+        // a directory cannot be moved into itself
+        if inode.is_dir && new_dir.is_under(&inode) {
+            return false;
+        }
+        if let Some(old_inode) = new_dir.entry(new_name) {
+            if Arc::ptr_eq(&old_inode, &inode) {
+                return true;
+            }
+            if inode.is_dir || old_inode.is_dir {
+                return false;
+            }
+            // only the inode actually removed loses a link
+            if let Some(old_inode) = new_dir.remove_entry(new_name) {
+                old_inode.inner.exclusive_access().links -= 1;
+            }
+        }
+        self.remove_entry(old_name);
+        new_dir.add_entry(new_name, inode.clone());
+        let mut inner = inode.inner.exclusive_access();
+        if let Content::Dir { parent, .. } = &mut inner.content {
+            *parent = new_dir.this.clone();
+        }
+        inner.ctime = get_time_ms() as u64;
+        true
+    }
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+}
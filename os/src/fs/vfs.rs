@@ -0,0 +1,71 @@
+use super::{Stat, StatMode};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+
+/// A dirent reported by `VfsInode::readdir`.
+pub struct DirEntry {
+    pub name: String,
+    /// inode number
+    pub ino: u64,
+    pub is_dir: bool,
+    /// Directory offset of the entry after this one.
+    pub next_offset: usize,
+}
+
+/// An inode of a mounted file system, whatever its kind. The changes a file
+/// system doesn't support fail by default, returning `None`, `false` or 0.
+pub trait VfsInode: Send + Sync {
+    /// Return the inode called `name` in this directory.
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>>;
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    /// Read the entries from directory offset `offset` on, empty if this is not a directory.
+    fn readdir(&self, offset: usize) -> Vec<DirEntry>;
+    fn stat(&self) -> Stat;
+    /// Let implementations find their own type in `link` and `rename`.
+    fn as_any(&self) -> &dyn Any;
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    /// Drop the content of a file.
+    fn truncate(&self) -> bool {
+        false
+    }
+    /// Create the file `name` in this directory, fail if it exists.
+    fn create(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    /// Create the directory `name` in this directory, fail if it exists.
+    fn mkdir(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    /// Remove the file `name` from this directory.
+    fn unlink(&self, _name: &str) -> bool {
+        false
+    }
+    /// Remove the empty directory `name` from this directory.
+    fn rmdir(&self, _name: &str) -> bool {
+        false
+    }
+    /// Add a hard link `name` in this directory to the file `inode` on the same file system.
+    fn link(&self, _name: &str, _inode: &dyn VfsInode) -> bool {
+        false
+    }
+    /// Move `old_name` in this directory to `new_name` in `new_dir` on the same file system.
+    fn rename(&self, _old_name: &str, _new_dir: &dyn VfsInode, _new_name: &str) -> bool {
+        false
+    }
+    fn is_dir(&self) -> bool {
+        StatMode::from_bits_truncate(self.stat().mode).contains(StatMode::DIR)
+    }
+}
+
+/// A file system in the mount table.
+pub trait FileSystem: Send + Sync {
+    fn root(&self) -> Arc<dyn VfsInode>;
+    /// Write back everything changed so far.
+    fn sync(&self) {}
+    /// Return true if an inode other than the root is in use, which forbids unmounting.
+    fn busy(&self) -> bool;
+}
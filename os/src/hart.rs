@@ -0,0 +1,61 @@
+//! The harts the kernel runs on. The hart ID of each is kept in `tp`,
+//! which user space doesn't get to change, see `__alltraps`.
+
+use crate::config::{BOOT_STACK_SIZE, MAX_HARTS, PAGE_SIZE};
+use crate::sbi::{hart_start, remote_sfence_vma};
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::info;
+
+/// Bit `i` is set once hart `i` runs with the kernel page table.
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn hart_id() -> usize {
+    let hart_id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) hart_id);
+    }
+    hart_id
+}
+
+pub fn set_online() {
+    ONLINE_HARTS.fetch_or(1 << hart_id(), Ordering::AcqRel);
+}
+
+/// IDs of the harts online.
+pub fn online_harts() -> impl Iterator<Item = usize> {
+    let online = ONLINE_HARTS.load(Ordering::Acquire);
+    (0..MAX_HARTS).filter(move |hart| online & (1 << hart) != 0)
+}
+
+/// Start every hart but this one at `_start_secondary`, those not present fail to start.
+pub fn start_other_harts() {
+    extern "C" {
+        fn _start_secondary();
+    }
+    for hart in (0..MAX_HARTS).filter(|&hart| hart != hart_id()) {
+        if hart_start(hart, _start_secondary as usize) {
+            info!("KERN: hart {} started", hart);
+        }
+    }
+}
+
+/// Top of the stack this hart booted on.
+pub fn boot_stack_top() -> usize {
+    extern "C" {
+        fn boot_stack_top();
+    }
+    boot_stack_top as usize - hart_id() * BOOT_STACK_SIZE
+}
+
+/// Drop the translation of the page at `va` from the TLB of every hart online,
+/// after its mapping was removed or lost permissions.
+pub fn flush_tlb(va: usize) {
+    unsafe {
+        asm!("sfence.vma {}", in(reg) va);
+    }
+    let others = ONLINE_HARTS.load(Ordering::Acquire) & !(1 << hart_id());
+    if others != 0 {
+        remote_sfence_vma(others, va, PAGE_SIZE);
+    }
+}
@@ -20,6 +20,7 @@ mod console;
 mod config;
 mod drivers;
 mod fs;
+mod hart;
 mod lang_items;
 mod logging;
 mod mm;
@@ -48,11 +49,11 @@ fn clear_bss() {
 }
 
 use lazy_static::*;
-use sync::UPIntrFreeCell;
+use sync::IntrSpinLock;
 
 lazy_static! {
-    pub static ref DEV_NON_BLOCKING_ACCESS: UPIntrFreeCell<bool> =
-        unsafe { UPIntrFreeCell::new(false) };
+    pub static ref DEV_NON_BLOCKING_ACCESS: IntrSpinLock<bool> =
+        unsafe { IntrSpinLock::new(false) };
 }
 
 #[no_mangle]
@@ -72,9 +73,25 @@ pub fn rust_main() -> ! {
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
     board::device_init();
+    board::hart_init();
+    hart::set_online();
     fs::list_apps();
     task::add_initproc();
     *DEV_NON_BLOCKING_ACCESS.exclusive_access() = true;
+    hart::start_other_harts();
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
+
+/// Where the harts started by `hart::start_other_harts` go after `_start_secondary`.
+#[no_mangle]
+pub fn rust_main_secondary() -> ! {
+    mm::init_hart();
+    trap::init();
+    trap::enable_timer_interrupt();
+    timer::set_next_trigger();
+    board::hart_init();
+    hart::set_online();
+    task::run_tasks();
+    panic!("Unreachable in rust_main_secondary!");
+}
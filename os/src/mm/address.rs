@@ -226,6 +226,12 @@ where
     pub fn get_end(&self) -> T {
         self.r
     }
+    pub fn contains(&self, v: T) -> bool {
+        self.l <= v && v < self.r
+    }
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.l < other.r && other.l < self.r
+    }
 }
 impl<T> IntoIterator for SimpleRange<T>
 where
@@ -2,12 +2,13 @@ use super::{frame_alloc, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
-use crate::sync::UPIntrFreeCell;
+use crate::config::{MEMORY_END, MMAP_BASE, MMAP_END, MMIO, PAGE_SIZE, TRAMPOLINE};
+use crate::sync::IntrSpinLock;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::cmp::Ordering;
 use lazy_static::*;
 use riscv::register::satp;
 
@@ -25,8 +26,8 @@ extern "C" {
 }
 
 lazy_static! {
-    pub static ref KERNEL_SPACE: Arc<UPIntrFreeCell<MemorySet>> =
-        Arc::new(unsafe { UPIntrFreeCell::new(MemorySet::new_kernel()) });
+    pub static ref KERNEL_SPACE: Arc<IntrSpinLock<MemorySet>> =
+        Arc::new(unsafe { IntrSpinLock::new(MemorySet::new_kernel()) });
 }
 
 pub fn kernel_token() -> usize {
@@ -36,6 +37,12 @@ pub fn kernel_token() -> usize {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// The heap starts right after the last segment of the elf.
+    heap_bottom: usize,
+    /// Current program break, the heap area covers `[heap_bottom, brk)`.
+    brk: usize,
+    /// Start of the area the break moves the end of, `None` while there is none.
+    heap_area: Option<VirtPageNum>,
 }
 
 impl MemorySet {
@@ -43,12 +50,23 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            heap_bottom: 0,
+            brk: 0,
+            heap_area: None,
         }
     }
     pub fn token(&self) -> usize {
         self.page_table.token()
     }
-    /// Assume that no conflicts.
+    /// Frames of this area are allocated on first touch.
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        self.push(MapArea::new_lazy(start_va, end_va, permission, None), None);
+    }
     pub fn insert_framed_area(
         &mut self,
         start_va: VirtAddr,
@@ -72,15 +90,158 @@ impl MemorySet {
         }
     }
     /// Add a new MapArea into this MemorySet.
-    /// Assuming that there are no conflicts in the virtual address
-    /// space.
+    /// Panic if it overlaps an existing area, use `is_free`/`unmap_range` first.
     pub fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
+        assert!(
+            self.is_free(map_area.vpn_range),
+            "{:?}..{:?} overlaps an existing area",
+            map_area.vpn_range.get_start(),
+            map_area.vpn_range.get_end()
+        );
         map_area.map(&mut self.page_table);
         if let Some(data) = data {
             map_area.copy_data(&self.page_table, data);
         }
         self.areas.push(map_area);
     }
+    /// Return true if no area overlaps `range`.
+    pub fn is_free(&self, range: VPNRange) -> bool {
+        !self
+            .areas
+            .iter()
+            .any(|area| area.vpn_range.overlaps(&range))
+    }
+    /// Return true if every page of `range` belongs to a user area.
+    pub fn is_user_mapped(&self, range: VPNRange) -> bool {
+        let mut covered = 0;
+        for area in self.areas.iter() {
+            if !area.vpn_range.overlaps(&range) {
+                continue;
+            }
+            if !area.map_perm.contains(MapPermission::U) {
+                return false;
+            }
+            let start = area.vpn_range.get_start().max(range.get_start());
+            let end = area.vpn_range.get_end().min(range.get_end());
+            covered += end.0 - start.0;
+        }
+        covered == range.get_end().0 - range.get_start().0
+    }
+    /// Find `page_count` free pages between `MMAP_BASE` and `MMAP_END`.
+    pub fn find_free_area(&self, page_count: usize) -> Option<VirtPageNum> {
+        let limit = VirtAddr::from(MMAP_END).floor();
+        let mut start = VirtAddr::from(MMAP_BASE).floor();
+        loop {
+            let end = VirtPageNum(start.0 + page_count);
+            if end > limit {
+                return None;
+            }
+            let range = VPNRange::new(start, end);
+            match self
+                .areas
+                .iter()
+                .filter(|area| area.vpn_range.overlaps(&range))
+                .map(|area| area.vpn_range.get_end())
+                .max()
+            {
+                Some(next) => start = next,
+                None => return Some(start),
+            }
+        }
+    }
+    /// Cut the parts of the areas that lie in `range` out of this MemorySet,
+    /// the returned pieces are still mapped in the page table.
+    fn take_range(&mut self, range: VPNRange) -> Vec<MapArea> {
+        let mut taken = Vec::new();
+        let mut kept = Vec::new();
+        for mut area in self.areas.drain(..) {
+            if !area.vpn_range.overlaps(&range) {
+                kept.push(area);
+                continue;
+            }
+            if area.vpn_range.get_end() > range.get_end() {
+                kept.push(area.split_off(range.get_end()));
+            }
+            if area.vpn_range.get_start() < range.get_start() {
+                let inner = area.split_off(range.get_start());
+                kept.push(area);
+                area = inner;
+            }
+            taken.push(area);
+        }
+        self.areas = kept;
+        taken
+    }
+    /// Unmap every user page in `range`, splitting the areas it cuts through.
+    /// Return false and change nothing if `range` covers a kernel-only area.
+    pub fn unmap_range(&mut self, range: VPNRange) -> bool {
+        if self.areas.iter().any(|area| {
+            area.vpn_range.overlaps(&range) && !area.map_perm.contains(MapPermission::U)
+        }) {
+            return false;
+        }
+        for mut area in self.take_range(range) {
+            area.unmap(&mut self.page_table);
+        }
+        true
+    }
+    /// Change the permission of `range`, which must be fully mapped by user areas.
+    pub fn protect_range(&mut self, range: VPNRange, permission: MapPermission) -> bool {
+        if !self.is_user_mapped(range) {
+            return false;
+        }
+        for mut area in self.take_range(range) {
+            area.set_perm(&mut self.page_table, permission);
+            self.areas.push(area);
+        }
+        true
+    }
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+    /// Move the program break to `new_brk`, heap pages are allocated on first touch.
+    /// Return the new break, or the old one if the heap would go below its bottom
+    /// or run into another area.
+    pub fn set_brk(&mut self, new_brk: usize) -> usize {
+        if new_brk < self.heap_bottom || new_brk > MMAP_BASE {
+            return self.brk;
+        }
+        let old_end = VirtAddr::from(self.brk).ceil();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        match new_end.cmp(&old_end) {
+            Ordering::Greater => {
+                let range = VPNRange::new(old_end, new_end);
+                if !self.is_free(range) {
+                    return self.brk;
+                }
+                // grow the heap area unless munmap or mprotect cut its end off
+                let heap_area = self.heap_area.and_then(|start| {
+                    self.areas.iter_mut().find(|area| {
+                        area.vpn_range.get_start() == start && area.vpn_range.get_end() == old_end
+                    })
+                });
+                match heap_area {
+                    Some(area) => {
+                        area.vpn_range = VPNRange::new(area.vpn_range.get_start(), new_end)
+                    }
+                    None => {
+                        let heap_perm = MapPermission::R | MapPermission::W | MapPermission::U;
+                        self.insert_lazy_area(old_end.into(), new_end.into(), heap_perm);
+                        self.heap_area = Some(old_end);
+                    }
+                }
+            }
+            Ordering::Less => {
+                self.unmap_range(VPNRange::new(new_end, old_end));
+                if self.heap_area.map_or(false, |start| start >= new_end) {
+                    self.heap_area = None;
+                }
+            }
+            Ordering::Equal => {}
+        }
+        self.brk = new_brk;
+        new_brk
+    }
     /// Mention that trampoline is not collected by areas.
     fn map_trampoline(&mut self) {
         self.page_table.map(
@@ -167,8 +328,9 @@ impl MemorySet {
         memory_set
     }
     /// Include sections in elf and trampoline,
-    /// also returns user_sp_base and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    /// also returns entry point.
+    /// Segments are loaded on first touch from a copy of their bytes in `elf_data`.
+    pub fn from_elf(elf_data: &[u8]) -> (Self, usize) {
         let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline();
@@ -195,42 +357,142 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+                let offset = ph.offset() as usize;
+                let len = ph.file_size() as usize;
+                let source = AreaSource {
+                    data: Arc::new(elf_data[offset..offset + len].to_vec()),
+                    offset: 0,
+                    len,
+                };
+                let map_area = MapArea::new_lazy(start_va, end_va, map_perm, Some(source));
                 max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                memory_set.push(map_area, None);
             }
         }
         let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_base: usize = max_end_va.into();
-        user_stack_base += PAGE_SIZE;
-        (
-            memory_set,
-            user_stack_base,
-            elf.header.pt2.entry_point() as usize,
-        )
-    }
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
+        memory_set.heap_bottom = max_end_va.into();
+        memory_set.brk = memory_set.heap_bottom;
+        (memory_set, elf.header.pt2.entry_point() as usize)
+    }
+    /// Clone a user space for fork.
+    /// Framed areas visible to U-mode share their frames with `user_space`
+    /// and are mapped read-only on both sides, the first write to a page
+    /// copies it in `handle_page_fault`. Trap contexts are accessed by the
+    /// trampoline in S-mode so they are still copied at once.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> MemorySet {
         let mut memory_set = Self::new_bare();
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
+        memory_set.heap_area = user_space.heap_area;
         // map trampoline
         memory_set.map_trampoline();
-        // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            // copy data from another space
-            for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
-                dst_ppn
-                    .get_bytes_array()
-                    .copy_from_slice(src_ppn.get_bytes_array());
+            let mut new_area = MapArea::from_another(area);
+            if area.is_cow_shareable() {
+                // share data sections/user_stack
+                let pte_flags = area.pte_flags() - PTEFlags::W;
+                for (&vpn, frame) in area.data_frames.iter() {
+                    memory_set.page_table.map(vpn, frame.ppn, pte_flags);
+                    user_space.page_table.set_flags(vpn, pte_flags);
+                    new_area.data_frames.insert(vpn, Arc::clone(frame));
+                }
+                memory_set.areas.push(new_area);
+            } else {
+                // copy trap_context and other framed areas
+                memory_set.push(new_area, None);
+                if area.map_type != MapType::Framed {
+                    continue;
+                }
+                for vpn in area.vpn_range {
+                    let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                    let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                    dst_ppn
+                        .get_bytes_array()
+                        .copy_from_slice(src_ppn.get_bytes_array());
+                }
             }
         }
         memory_set
     }
+    /// Try to resolve a page fault at `va`.
+    /// Return false if the access is not allowed by any area.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, write: bool) -> bool {
+        let vpn = va.floor();
+        let area = match self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.contains(vpn))
+        {
+            Some(area) => area,
+            None => return false,
+        };
+        if write && !area.map_perm.contains(MapPermission::W) {
+            return false;
+        }
+        match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() => {
+                if !write || pte.writable() || !area.is_cow_shareable() {
+                    return false;
+                }
+                area.copy_on_write(&mut self.page_table, vpn);
+                true
+            }
+            _ => {
+                if !area.lazy {
+                    return false;
+                }
+                area.map_one(&mut self.page_table, vpn);
+                true
+            }
+        }
+    }
+    /// Resolve the faults the kernel would hit when it accesses
+    /// `[start, start + len)` of this space through physical addresses,
+    /// e.g. break shared pages before a syscall writes to them.
+    /// Return false if a page can't be made user accessible, and writable for `write`.
+    pub fn fault_in(&mut self, start: usize, len: usize, write: bool) -> bool {
+        if len == 0 {
+            return true;
+        }
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(end).ceil();
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            if self.user_accessible(vpn, write) {
+                continue;
+            }
+            if !self.handle_page_fault(vpn.into(), write) || !self.user_accessible(vpn, write) {
+                return false;
+            }
+        }
+        true
+    }
+    /// Same as `fault_in` for a string ending with `\0`.
+    pub fn fault_in_str(&mut self, ptr: usize) -> bool {
+        let mut va = VirtAddr::from(ptr);
+        loop {
+            if !self.fault_in(va.into(), 1, false) {
+                return false;
+            }
+            let ppn = self.page_table.translate(va.floor()).unwrap().ppn();
+            if ppn.get_bytes_array()[va.page_offset()..].contains(&0) {
+                return true;
+            }
+            let mut next_vpn = va.floor();
+            next_vpn.step();
+            va = next_vpn.into();
+        }
+    }
+    /// Whether user mode may access `vpn`, and write it for `write`.
+    fn user_accessible(&self, vpn: VirtPageNum, write: bool) -> bool {
+        match self.page_table.translate(vpn) {
+            Some(pte) => pte.is_valid() && pte.user() && (!write || pte.writable()),
+            None => false,
+        }
+    }
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
@@ -249,9 +511,35 @@ impl MemorySet {
 
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    /// Frames may be shared with other spaces after fork.
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// Frames are allocated on first touch instead of in `map`.
+    lazy: bool,
+    /// Initial contents of a lazy area, dropped once every page is present.
+    source: Option<AreaSource>,
+}
+
+/// Bytes loaded into a lazy area on first touch, starting at its first page.
+/// Pages past `len` are zero-filled.
+#[derive(Clone)]
+pub struct AreaSource {
+    pub data: Arc<Vec<u8>>,
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl AreaSource {
+    /// Copy the part of the source that belongs to the `page_idx`-th page.
+    fn fill(&self, page_idx: usize, dst: &mut [u8]) {
+        let start = page_idx * PAGE_SIZE;
+        if start >= self.len {
+            return;
+        }
+        let end = self.len.min(start + PAGE_SIZE);
+        dst[..end - start].copy_from_slice(&self.data[self.offset + start..self.offset + end]);
+    }
 }
 
 impl MapArea {
@@ -268,14 +556,30 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            lazy: false,
+            source: None,
         }
     }
+    /// A framed area whose pages are filled from `source` on first touch.
+    pub fn new_lazy(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_perm: MapPermission,
+        source: Option<AreaSource>,
+    ) -> Self {
+        let mut area = Self::new(start_va, end_va, MapType::Framed, map_perm);
+        area.lazy = true;
+        area.source = source;
+        area
+    }
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            lazy: another.lazy,
+            source: another.source.clone(),
         }
     }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
@@ -287,7 +591,12 @@ impl MapArea {
             MapType::Framed => {
                 let frame = frame_alloc().unwrap();
                 ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                if let Some(source) = &self.source {
+                    let page_idx = vpn.0 - self.vpn_range.get_start().0;
+                    source.fill(page_idx, ppn.get_bytes_array());
+                }
+                self.data_frames.insert(vpn, Arc::new(frame));
+                self.drop_loaded_source();
             }
             MapType::Linear(pn_offset) => {
                 // check for sv39
@@ -295,16 +604,94 @@ impl MapArea {
                 ppn = PhysPageNum((vpn.0 as isize + pn_offset) as usize);
             }
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        page_table.map(vpn, ppn, self.pte_flags());
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        if self.map_type == MapType::Framed && self.data_frames.remove(&vpn).is_none() {
+            // a lazy page that has never been touched
+            return;
+        }
+        page_table.unmap(vpn);
+    }
+    fn pte_flags(&self) -> PTEFlags {
+        PTEFlags::from_bits(self.map_perm.bits).unwrap()
+    }
+    /// Only user-visible framed areas are shared on fork.
+    fn is_cow_shareable(&self) -> bool {
+        self.map_type == MapType::Framed && self.map_perm.contains(MapPermission::U)
+    }
+    /// Split this area at `vpn`, `self` keeps the pages below it and
+    /// the rest is returned.
+    fn split_off(&mut self, vpn: VirtPageNum) -> MapArea {
+        let start = self.vpn_range.get_start();
+        let end = self.vpn_range.get_end();
+        assert!(start < vpn && vpn < end);
+        let shift = (vpn.0 - start.0) * PAGE_SIZE;
+        let source = self.source.as_ref().map(|source| AreaSource {
+            data: Arc::clone(&source.data),
+            offset: source.offset + shift.min(source.len),
+            len: source.len.saturating_sub(shift),
+        });
+        self.vpn_range = VPNRange::new(start, vpn);
+        let mut area = MapArea {
+            vpn_range: VPNRange::new(vpn, end),
+            data_frames: self.data_frames.split_off(&vpn),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            lazy: self.lazy,
+            source,
+        };
+        self.drop_loaded_source();
+        area.drop_loaded_source();
+        area
+    }
+    /// Nothing is read from the source any more once every page is present.
+    fn drop_loaded_source(&mut self) {
+        let pages = self.vpn_range.get_end().0 - self.vpn_range.get_start().0;
+        if self.data_frames.len() == pages {
+            self.source = None;
+        }
+    }
+    /// Change the permission of this area and of its mapped pages,
+    /// shared pages stay read-only until they are copied.
+    fn set_perm(&mut self, page_table: &mut PageTable, map_perm: MapPermission) {
+        self.map_perm = map_perm;
+        let pte_flags = self.pte_flags();
         if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+            for (&vpn, frame) in self.data_frames.iter() {
+                if Arc::strong_count(frame) > 1 {
+                    page_table.set_flags(vpn, pte_flags - PTEFlags::W);
+                } else {
+                    page_table.set_flags(vpn, pte_flags);
+                }
+            }
+        } else {
+            for vpn in self.vpn_range {
+                page_table.set_flags(vpn, pte_flags);
+            }
         }
+    }
+    /// Give `vpn` a private writable frame. The old frame is copied only if
+    /// another space still holds it.
+    fn copy_on_write(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let frame = self.data_frames.get(&vpn).unwrap();
+        if Arc::strong_count(frame) == 1 {
+            page_table.set_flags(vpn, self.pte_flags());
+            return;
+        }
+        let new_frame = frame_alloc().unwrap();
+        new_frame
+            .ppn
+            .get_bytes_array()
+            .copy_from_slice(frame.ppn.get_bytes_array());
         page_table.unmap(vpn);
+        page_table.map(vpn, new_frame.ppn, self.pte_flags());
+        self.data_frames.insert(vpn, Arc::new(new_frame));
     }
     pub fn map(&mut self, page_table: &mut PageTable) {
+        if self.lazy {
+            return;
+        }
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn);
         }
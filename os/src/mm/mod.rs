@@ -10,12 +10,17 @@ pub use frame_allocator::{frame_alloc, frame_alloc_more, frame_dealloc, FrameTra
 pub use memory_set::{kernel_token, MapArea, MapPermission, MapType, MemorySet, KERNEL_SPACE};
 use page_table::PTEFlags;
 pub use page_table::{
-    translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
+    copy_to_user, translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
     PageTableEntry, UserBuffer,
 };
 
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
+    init_hart();
+}
+
+/// Turn on paging with the kernel page table on this hart.
+pub fn init_hart() {
     KERNEL_SPACE.exclusive_access().activate();
 }
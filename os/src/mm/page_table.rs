@@ -1,4 +1,5 @@
 use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use crate::hart::flush_tlb;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
@@ -50,6 +51,9 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn user(&self) -> bool {
+        (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
 }
 
 pub struct PageTable {
@@ -120,6 +124,18 @@ impl PageTable {
         let pte = self.find_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        flush_tlb(VirtAddr::from(vpn).into());
+    }
+    /// Replace the flags of a valid mapping and keep its frame.
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(
+            pte.is_valid(),
+            "vpn {:?} is invalid before setting flags",
+            vpn
+        );
+        *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
+        flush_tlb(VirtAddr::from(vpn).into());
     }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).map(|pte| *pte)
@@ -159,6 +175,15 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     v
 }
 
+/// Copy `src` to user space at `dst`, the range may cross pages.
+pub fn copy_to_user(token: usize, dst: *mut u8, src: &[u8]) {
+    let mut copied = 0;
+    for buffer in translated_byte_buffer(token, dst, src.len()) {
+        buffer.copy_from_slice(&src[copied..copied + buffer.len()]);
+        copied += buffer.len();
+    }
+}
+
 /// Load a string from other address spaces into kernel space without an end `\0`.
 pub fn translated_str(token: usize, ptr: *const u8) -> String {
     let page_table = PageTable::from_token(token);
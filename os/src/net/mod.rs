@@ -11,17 +11,17 @@ use lose_net_stack::{results::Packet, LoseStack, MacAddress, TcpFlags};
 use crate::{
     drivers::NET_DEVICE,
     net::socket::{get_socket, push_data},
-    sync::UPIntrFreeCell,
+    sync::IntrSpinLock,
 };
 
 use self::{port_table::check_accept, socket::set_s_a_by_index};
 
-pub struct NetStack(UPIntrFreeCell<LoseStack>);
+pub struct NetStack(IntrSpinLock<LoseStack>);
 
 impl NetStack {
     pub fn new() -> Self {
         unsafe {
-            NetStack(UPIntrFreeCell::new(LoseStack::new(
+            NetStack(IntrSpinLock::new(LoseStack::new(
                 IPv4::new(10, 0, 2, 15),
                 MacAddress::new([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]),
             )))
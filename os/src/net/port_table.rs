@@ -3,7 +3,7 @@ use lazy_static::lazy_static;
 use lose_net_stack::packets::tcp::TCPPacket;
 
 use crate::fs::File;
-use crate::sync::UPIntrFreeCell;
+use crate::sync::IntrSpinLock;
 use crate::task::TaskControlBlock;
 
 use super::tcp::TCP;
@@ -15,8 +15,8 @@ pub struct Port {
 }
 
 lazy_static! {
-    static ref LISTEN_TABLE: UPIntrFreeCell<Vec<Option<Port>>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref LISTEN_TABLE: IntrSpinLock<Vec<Option<Port>>> =
+        unsafe { IntrSpinLock::new(Vec::new()) };
 }
 
 pub fn listen(port: u16) -> Option<usize> {
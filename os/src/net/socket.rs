@@ -3,7 +3,7 @@ use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use lose_net_stack::IPv4;
 
-use crate::sync::UPIntrFreeCell;
+use crate::sync::IntrSpinLock;
 
 // TODO: specify the protocol, TCP or UDP
 pub struct Socket {
@@ -16,8 +16,8 @@ pub struct Socket {
 }
 
 lazy_static! {
-    static ref SOCKET_TABLE: UPIntrFreeCell<Vec<Option<Socket>>> =
-        unsafe { UPIntrFreeCell::new(Vec::new()) };
+    static ref SOCKET_TABLE: IntrSpinLock<Vec<Option<Socket>>> =
+        unsafe { IntrSpinLock::new(Vec::new()) };
 }
 
 /// get the seq and ack by socket index
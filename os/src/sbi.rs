@@ -3,6 +3,18 @@ pub fn set_timer(timer: usize) {
     sbi_rt::set_timer(timer as _);
 }
 
+/// use sbi call to start `hartid` at `start_addr` with its hart ID in `a0`,
+/// return false if there is no such hart or it is running
+pub fn hart_start(hartid: usize, start_addr: usize) -> bool {
+    sbi_rt::hart_start(hartid, start_addr, 0).error == 0
+}
+
+/// use sbi call to flush the TLB entries of `size` bytes from `start_addr`
+/// on the harts set in `hart_mask`
+pub fn remote_sfence_vma(hart_mask: usize, start_addr: usize, size: usize) {
+    sbi_rt::remote_sfence_vma(hart_mask, 0, start_addr, size);
+}
+
 /// use sbi call to shutdown the kernel
 pub fn shutdown(failure: bool) -> ! {
     use sbi_rt::{system_reset, NoReason, Shutdown, SystemFailure};
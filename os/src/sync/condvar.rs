@@ -1,4 +1,4 @@
-use crate::sync::{Mutex, UPIntrFreeCell};
+use crate::sync::{IntrSpinLock, Mutex};
 use crate::task::{
     block_current_and_run_next, block_current_task, current_task, wakeup_task, TaskContext,
     TaskControlBlock,
@@ -6,7 +6,7 @@ use crate::task::{
 use alloc::{collections::VecDeque, sync::Arc};
 
 pub struct Condvar {
-    pub inner: UPIntrFreeCell<CondvarInner>,
+    pub inner: IntrSpinLock<CondvarInner>,
 }
 
 pub struct CondvarInner {
@@ -17,7 +17,7 @@ impl Condvar {
     pub fn new() -> Self {
         Self {
             inner: unsafe {
-                UPIntrFreeCell::new(CondvarInner {
+                IntrSpinLock::new(CondvarInner {
                     wait_queue: VecDeque::new(),
                 })
             },
@@ -1,9 +1,9 @@
 mod condvar;
 mod mutex;
 mod semaphore;
-mod up;
+mod spin;
 
 pub use condvar::Condvar;
 pub use mutex::{Mutex, MutexBlocking, MutexSpin};
 pub use semaphore::Semaphore;
-pub use up::{UPIntrFreeCell, UPIntrRefMut};
+pub use spin::{IntrSpinGuard, IntrSpinLock};
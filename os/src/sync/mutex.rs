@@ -1,4 +1,4 @@
-use super::UPIntrFreeCell;
+use super::IntrSpinLock;
 use crate::task::TaskControlBlock;
 use crate::task::{block_current_and_run_next, suspend_current_and_run_next};
 use crate::task::{current_task, wakeup_task};
@@ -10,13 +10,13 @@ pub trait Mutex: Sync + Send {
 }
 
 pub struct MutexSpin {
-    locked: UPIntrFreeCell<bool>,
+    locked: IntrSpinLock<bool>,
 }
 
 impl MutexSpin {
     pub fn new() -> Self {
         Self {
-            locked: unsafe { UPIntrFreeCell::new(false) },
+            locked: unsafe { IntrSpinLock::new(false) },
         }
     }
 }
@@ -43,7 +43,7 @@ impl Mutex for MutexSpin {
 }
 
 pub struct MutexBlocking {
-    inner: UPIntrFreeCell<MutexBlockingInner>,
+    inner: IntrSpinLock<MutexBlockingInner>,
 }
 
 pub struct MutexBlockingInner {
@@ -55,7 +55,7 @@ impl MutexBlocking {
     pub fn new() -> Self {
         Self {
             inner: unsafe {
-                UPIntrFreeCell::new(MutexBlockingInner {
+                IntrSpinLock::new(MutexBlockingInner {
                     locked: false,
                     wait_queue: VecDeque::new(),
                 })
@@ -0,0 +1,126 @@
+use crate::config::MAX_HARTS;
+use crate::hart::hart_id;
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use riscv::register::sstatus;
+
+pub struct IntrMaskingInfo {
+    nested_level: usize,
+    sie_before_masking: bool,
+}
+
+/// Masking info of each hart, only ever touched by its own hart with interrupts off.
+struct PerHartMasking([UnsafeCell<IntrMaskingInfo>; MAX_HARTS]);
+
+unsafe impl Sync for PerHartMasking {}
+
+static INTR_MASKING_INFO: PerHartMasking = PerHartMasking(
+    [const {
+        UnsafeCell::new(IntrMaskingInfo {
+            nested_level: 0,
+            sie_before_masking: false,
+        })
+    }; MAX_HARTS],
+);
+
+impl IntrMaskingInfo {
+    /// Mask interrupts on this hart, nested with the other locks it holds.
+    fn enter() {
+        let sie = sstatus::read().sie();
+        unsafe {
+            sstatus::clear_sie();
+        }
+        let info = unsafe { &mut *INTR_MASKING_INFO.0[hart_id()].get() };
+        if info.nested_level == 0 {
+            info.sie_before_masking = sie;
+        }
+        info.nested_level += 1;
+    }
+
+    fn exit() {
+        let info = unsafe { &mut *INTR_MASKING_INFO.0[hart_id()].get() };
+        info.nested_level -= 1;
+        if info.nested_level == 0 && info.sie_before_masking {
+            unsafe {
+                sstatus::set_sie();
+            }
+        }
+    }
+}
+
+const NO_OWNER: usize = usize::MAX;
+
+/// A spin lock that also masks interrupts on the hart holding it,
+/// so an interrupt handler never waits for the code it interrupted.
+pub struct IntrSpinLock<T> {
+    locked: AtomicBool,
+    /// Hart holding the lock, taking it twice on one hart panics rather than hangs.
+    owner: AtomicUsize,
+    inner: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for IntrSpinLock<T> {}
+
+pub struct IntrSpinGuard<'a, T> {
+    lock: &'a IntrSpinLock<T>,
+}
+
+impl<T> IntrSpinLock<T> {
+    /// User is responsible to guarantee that no guard is held across a task switch,
+    /// the thread may go on on another hart.
+    pub unsafe fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            owner: AtomicUsize::new(NO_OWNER),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Spin until the lock is free, panic if this hart holds it.
+    pub fn exclusive_access(&self) -> IntrSpinGuard<'_, T> {
+        IntrMaskingInfo::enter();
+        let hart = hart_id();
+        if self.owner.load(Ordering::Relaxed) == hart {
+            panic!("lock already held on hart {}", hart);
+        }
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+        self.owner.store(hart, Ordering::Relaxed);
+        IntrSpinGuard { lock: self }
+    }
+
+    pub fn exclusive_session<F, V>(&self, f: F) -> V
+    where
+        F: FnOnce(&mut T) -> V,
+    {
+        let mut inner = self.exclusive_access();
+        f(inner.deref_mut())
+    }
+}
+
+impl<'a, T> Drop for IntrSpinGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.owner.store(NO_OWNER, Ordering::Relaxed);
+        self.lock.locked.store(false, Ordering::Release);
+        IntrMaskingInfo::exit();
+    }
+}
+
+impl<'a, T> Deref for IntrSpinGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+impl<'a, T> DerefMut for IntrSpinGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
@@ -1,12 +1,20 @@
-use crate::fs::{make_pipe, open_file, OpenFlags};
-use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
+use crate::config::PAGE_SIZE;
+use crate::fs::{
+    dir_exists, link_file, make_dir, make_pipe, mount, normalize_path, open_file, rename_file,
+    stat_file, sync_all, umount, unlink_file, File, OpenFlags, SeekFrom, Stat,
+};
+use crate::mm::{
+    copy_to_user, translated_byte_buffer, translated_refmut, translated_str, UserBuffer,
+};
 use crate::task::{current_process, current_user_token};
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
     let process = current_process();
-    let inner = process.inner_exclusive_access();
+    let mut inner = process.inner_exclusive_access();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -15,6 +23,9 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
             return -1;
         }
         let file = file.clone();
+        if !inner.memory_set.fault_in(buf as usize, len, false) {
+            return -1;
+        }
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
         file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
@@ -26,7 +37,7 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
 pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
     let process = current_process();
-    let inner = process.inner_exclusive_access();
+    let mut inner = process.inner_exclusive_access();
     if fd >= inner.fd_table.len() {
         return -1;
     }
@@ -35,6 +46,9 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         if !file.readable() {
             return -1;
         }
+        if !inner.memory_set.fault_in(buf as usize, len, true) {
+            return -1;
+        }
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
         file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
@@ -45,8 +59,10 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
 
 pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
-    let token = current_user_token();
-    let path = translated_str(token, path);
+    let path = match user_path(path) {
+        Some(path) => path,
+        None => return -1,
+    };
     if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
@@ -74,6 +90,12 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     let process = current_process();
     let token = current_user_token();
     let mut inner = process.inner_exclusive_access();
+    if !inner
+        .memory_set
+        .fault_in(pipe as usize, 2 * core::mem::size_of::<usize>(), true)
+    {
+        return -1;
+    }
     let (pipe_read, pipe_write) = make_pipe();
     let read_fd = inner.alloc_fd();
     inner.fd_table[read_fd] = Some(pipe_read);
@@ -97,3 +119,290 @@ pub fn sys_dup(fd: usize) -> isize {
     inner.fd_table[new_fd] = Some(Arc::clone(inner.fd_table[fd].as_ref().unwrap()));
     new_fd as isize
 }
+
+/// Relative paths of the `*at` syscalls start from the working directory.
+const AT_FDCWD: isize = -100;
+const AT_REMOVEDIR: u32 = 0x200;
+
+/// Translate the user string at `path` into a normalized absolute path.
+/// Only `AT_FDCWD` is supported as `dirfd` of a relative path,
+/// return None for others or if `path` isn't readable.
+fn user_path_at(dirfd: isize, path: *const u8) -> Option<String> {
+    let process = current_process();
+    let token = current_user_token();
+    let mut inner = process.inner_exclusive_access();
+    if !inner.memory_set.fault_in_str(path as usize) {
+        return None;
+    }
+    let path = translated_str(token, path);
+    if dirfd != AT_FDCWD && !path.starts_with('/') {
+        return None;
+    }
+    Some(normalize_path(&inner.cwd, &path))
+}
+
+fn user_path(path: *const u8) -> Option<String> {
+    user_path_at(AT_FDCWD, path)
+}
+
+pub fn sys_mkdir(path: *const u8) -> isize {
+    match user_path(path) {
+        Some(path) if make_dir(&path) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_chdir(path: *const u8) -> isize {
+    let path = match user_path(path) {
+        Some(path) if dir_exists(&path) => path,
+        _ => return -1,
+    };
+    current_process().inner_exclusive_access().cwd = path;
+    0
+}
+
+/// Copy the working directory with a trailing `\0` into `buf`,
+/// return its length or -1 if `buf` is too small.
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let process = current_process();
+    let token = current_user_token();
+    let mut inner = process.inner_exclusive_access();
+    let cwd_len = inner.cwd.len();
+    if cwd_len + 1 > len {
+        return -1;
+    }
+    if !inner.memory_set.fault_in(buf as usize, cwd_len + 1, true) {
+        return -1;
+    }
+    let mut cwd = inner.cwd.clone();
+    drop(inner);
+    cwd.push('\0');
+    copy_to_user(token, buf, cwd.as_bytes());
+    cwd_len as isize
+}
+
+pub fn sys_unlinkat(dirfd: isize, path: *const u8, flags: u32) -> isize {
+    if flags & !AT_REMOVEDIR != 0 {
+        return -1;
+    }
+    match user_path_at(dirfd, path) {
+        Some(path) if unlink_file(&path, flags & AT_REMOVEDIR != 0) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_linkat(
+    old_dirfd: isize,
+    old_path: *const u8,
+    new_dirfd: isize,
+    new_path: *const u8,
+    flags: u32,
+) -> isize {
+    if flags != 0 {
+        return -1;
+    }
+    match (
+        user_path_at(old_dirfd, old_path),
+        user_path_at(new_dirfd, new_path),
+    ) {
+        (Some(old_path), Some(new_path)) if link_file(&old_path, &new_path) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_renameat(
+    old_dirfd: isize,
+    old_path: *const u8,
+    new_dirfd: isize,
+    new_path: *const u8,
+) -> isize {
+    match (
+        user_path_at(old_dirfd, old_path),
+        user_path_at(new_dirfd, new_path),
+    ) {
+        (Some(old_path), Some(new_path)) if rename_file(&old_path, &new_path) => 0,
+        _ => -1,
+    }
+}
+
+/// Copy `stat` to user space at `st`, return false if `st` isn't writable.
+fn copy_stat(st: *mut Stat, stat: &Stat) -> bool {
+    let process = current_process();
+    let token = current_user_token();
+    if !process.inner_exclusive_access().memory_set.fault_in(
+        st as usize,
+        core::mem::size_of::<Stat>(),
+        true,
+    ) {
+        return false;
+    }
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            stat as *const Stat as *const u8,
+            core::mem::size_of::<Stat>(),
+        )
+    };
+    copy_to_user(token, st as *mut u8, bytes);
+    true
+}
+
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -1,
+    };
+    drop(inner);
+    match file.stat() {
+        Some(stat) if copy_stat(st, &stat) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_sync() -> isize {
+    sync_all();
+    0
+}
+
+/// Mount the block device called `source`, such as `vdb`, on the directory `target`.
+pub fn sys_mount(source: *const u8, target: *const u8) -> isize {
+    let source = {
+        let token = current_user_token();
+        let process = current_process();
+        let mut inner = process.inner_exclusive_access();
+        if !inner.memory_set.fault_in_str(source as usize) {
+            return -1;
+        }
+        translated_str(token, source)
+    };
+    match user_path(target) {
+        Some(target) if mount(&source, &target) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_umount(target: *const u8) -> isize {
+    match user_path(target) {
+        Some(target) if umount(&target) => 0,
+        _ => -1,
+    }
+}
+
+pub fn sys_stat(path: *const u8, st: *mut Stat) -> isize {
+    match user_path(path).and_then(|path| stat_file(&path)) {
+        Some(stat) if copy_stat(st, &stat) => 0,
+        _ => -1,
+    }
+}
+
+/// Read directory records from the directory `fd` into `buf`,
+/// return the bytes written or 0 at the end of the directory.
+pub fn sys_getdents64(fd: usize, buf: *mut u8, len: usize) -> isize {
+    // a short batch is fine, the caller keeps reading until 0
+    let len = len.min(PAGE_SIZE);
+    let process = current_process();
+    let token = current_user_token();
+    let mut inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -1,
+    };
+    if !inner.memory_set.fault_in(buf as usize, len, true) {
+        return -1;
+    }
+    drop(inner);
+    let mut records = vec![0u8; len];
+    match file.read_dirents(&mut records) {
+        Some(size) => {
+            copy_to_user(token, buf, &records[..size]);
+            size as isize
+        }
+        None => -1,
+    }
+}
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => SeekFrom::Start(offset as usize),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return -1,
+    };
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(fd) {
+        Some(Some(file)) => file.clone(),
+        _ => return -1,
+    };
+    drop(inner);
+    match file.seek(pos) {
+        Some(offset) => offset as isize,
+        None => -1,
+    }
+}
+
+/// Return the file at `fd` if it is seekable and allows the access.
+fn seekable_file(fd: usize, write: bool) -> Option<Arc<dyn File + Send + Sync>> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = inner.fd_table.get(fd)?.as_ref()?.clone();
+    drop(inner);
+    let allowed = if write {
+        file.writable()
+    } else {
+        file.readable()
+    };
+    if !allowed || file.seek(SeekFrom::Current(0)).is_none() {
+        return None;
+    }
+    Some(file)
+}
+
+pub fn sys_pread(fd: usize, buf: *mut u8, len: usize, offset: usize) -> isize {
+    let file = match seekable_file(fd, false) {
+        Some(file) => file,
+        None => return -1,
+    };
+    let token = current_user_token();
+    if !current_process()
+        .inner_exclusive_access()
+        .memory_set
+        .fault_in(buf as usize, len, true)
+    {
+        return -1;
+    }
+    let mut total_read_size = 0usize;
+    for slice in translated_byte_buffer(token, buf, len) {
+        let read_size = file.read_at(offset + total_read_size, slice).unwrap();
+        total_read_size += read_size;
+        if read_size < slice.len() {
+            break;
+        }
+    }
+    total_read_size as isize
+}
+
+pub fn sys_pwrite(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let file = match seekable_file(fd, true) {
+        Some(file) => file,
+        None => return -1,
+    };
+    let token = current_user_token();
+    if !current_process()
+        .inner_exclusive_access()
+        .memory_set
+        .fault_in(buf as usize, len, false)
+    {
+        return -1;
+    }
+    let mut total_write_size = 0usize;
+    for slice in translated_byte_buffer(token, buf, len) {
+        total_write_size += file.write_at(offset + total_write_size, slice).unwrap();
+    }
+    total_write_size as isize
+}
@@ -0,0 +1,173 @@
+use crate::config::{MMAP_END, PAGE_SIZE};
+use crate::mm::{MapArea, MapPermission, MapType, VPNRange, VirtAddr, VirtPageNum};
+use crate::task::current_process;
+use alloc::vec;
+
+bitflags! {
+    struct MmapProt: u32 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    struct MmapFlags: u32 {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+impl From<MmapProt> for MapPermission {
+    fn from(prot: MmapProt) -> Self {
+        let mut perm = MapPermission::U;
+        // RISC-V does not allow writable pages that are not readable
+        if prot.intersects(MmapProt::READ | MmapProt::WRITE) {
+            perm |= MapPermission::R;
+        }
+        if prot.contains(MmapProt::WRITE) {
+            perm |= MapPermission::W;
+        }
+        if prot.contains(MmapProt::EXEC) {
+            perm |= MapPermission::X;
+        }
+        perm
+    }
+}
+
+/// Return the page range of `[addr, addr + len)`, or `None` if `addr` is not
+/// page aligned, the range is empty or it reaches past `MMAP_END`.
+fn page_range(addr: usize, len: usize) -> Option<VPNRange> {
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return None;
+    }
+    let end = addr.checked_add(len)?;
+    if end > MMAP_END {
+        return None;
+    }
+    Some(VPNRange::new(
+        VirtAddr::from(addr).floor(),
+        VirtAddr::from(end).ceil(),
+    ))
+}
+
+/// Only private anonymous mappings and read-only file mappings are supported.
+pub fn sys_mmap(addr: usize, len: usize, prot: u32, flags: u32, fd: usize, offset: usize) -> isize {
+    let (prot, flags) = match (MmapProt::from_bits(prot), MmapFlags::from_bits(flags)) {
+        (Some(prot), Some(flags)) => (prot, flags),
+        _ => return -1,
+    };
+    if flags.contains(MmapFlags::SHARED) == flags.contains(MmapFlags::PRIVATE) {
+        return -1;
+    }
+    if len == 0 || offset % PAGE_SIZE != 0 || (flags.contains(MmapFlags::FIXED) && addr == 0) {
+        return -1;
+    }
+    let page_count = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    let process = current_process();
+    // load the file before choosing the range since reading may block
+    let data = if flags.contains(MmapFlags::ANONYMOUS) {
+        if flags.contains(MmapFlags::SHARED) {
+            return -1;
+        }
+        None
+    } else {
+        if prot.contains(MmapProt::WRITE) {
+            return -1;
+        }
+        let inner = process.inner_exclusive_access();
+        let file = match inner.fd_table.get(fd) {
+            Some(Some(file)) if file.readable() => file.clone(),
+            _ => return -1,
+        };
+        drop(inner);
+        let mut data = vec![0u8; page_count * PAGE_SIZE];
+        match file.read_at(offset, &mut data) {
+            Some(read_size) => data.truncate(read_size),
+            None => return -1,
+        }
+        Some(data)
+    };
+    let mut inner = process.inner_exclusive_access();
+    let memory_set = &mut inner.memory_set;
+    let start_vpn = match page_range(addr, len) {
+        Some(range) if flags.contains(MmapFlags::FIXED) => {
+            if !memory_set.unmap_range(range) {
+                return -1;
+            }
+            range.get_start()
+        }
+        Some(range) if addr != 0 && memory_set.is_free(range) => range.get_start(),
+        _ => match memory_set.find_free_area(page_count) {
+            Some(start_vpn) => start_vpn,
+            None => return -1,
+        },
+    };
+    let start_va: VirtAddr = start_vpn.into();
+    let end_va: VirtAddr = VirtPageNum(start_vpn.0 + page_count).into();
+    let perm = MapPermission::from(prot);
+    match data {
+        Some(data) => memory_set.push(
+            MapArea::new(start_va, end_va, MapType::Framed, perm),
+            Some(data.as_slice()),
+        ),
+        None => memory_set.insert_lazy_area(start_va, end_va, perm),
+    }
+    start_va.0 as isize
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    let range = match page_range(addr, len) {
+        Some(range) => range,
+        None => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner.memory_set.unmap_range(range) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: u32) -> isize {
+    let (range, prot) = match (page_range(addr, len), MmapProt::from_bits(prot)) {
+        (Some(range), Some(prot)) => (range, prot),
+        _ => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner.memory_set.protect_range(range, prot.into()) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Set the program break to `addr` and return the new break, which is the old one
+/// if it can't be moved there. `addr == 0` only queries it.
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if addr == 0 {
+        return inner.memory_set.brk() as isize;
+    }
+    inner.memory_set.set_brk(addr) as isize
+}
+
+/// Move the program break by `increment` bytes and return the old break.
+pub fn sys_sbrk(increment: isize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old_brk = inner.memory_set.brk();
+    let new_brk = match old_brk.checked_add_signed(increment) {
+        Some(new_brk) => new_brk,
+        None => return -1,
+    };
+    if inner.memory_set.set_brk(new_brk) != new_brk {
+        return -1;
+    }
+    old_brk as isize
+}
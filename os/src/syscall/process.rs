@@ -1,14 +1,19 @@
-use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str};
+use crate::config::USER_STACK_SIZE;
+use crate::fs::{normalize_path, open_file, OpenFlags};
+use crate::mm::{copy_to_user, translated_ref, translated_refmut, translated_str};
 use crate::task::{
     current_process, current_task, current_user_token, exit_current_and_run_next, pid2process,
-    suspend_current_and_run_next, SignalFlags,
+    suspend_current_and_run_next, ProcessControlBlock, Rusage, SignalFlags, Tms, MAX_PRIORITY,
+    MIN_PRIORITY,
 };
-use crate::timer::get_time_ms;
+use crate::timer::{get_time_ms, ticks_to_ms};
 use alloc::string::String;
-use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+const RUSAGE_SELF: isize = 0;
+const RUSAGE_CHILDREN: isize = -1;
+const RUSAGE_THREAD: isize = 1;
+
 pub fn sys_exit(exit_code: i32) -> ! {
     exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
@@ -19,10 +24,81 @@ pub fn sys_yield() -> isize {
     0
 }
 
+/// Set the priority of the current thread, threads it creates start with it.
+/// Return `prio`, or -1 if it is below `MIN_PRIORITY` or above `MAX_PRIORITY`.
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < MIN_PRIORITY as isize || prio > MAX_PRIORITY as isize {
+        return -1;
+    }
+    current_task().unwrap().inner_exclusive_access().priority = prio as usize;
+    prio
+}
+
 pub fn sys_get_time() -> isize {
     get_time_ms() as isize
 }
 
+/// Fill `tms` with the times of this process and of the children it waited for,
+/// return the time since boot, all in ms.
+pub fn sys_times(tms: *mut Tms) -> isize {
+    let process = current_process();
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .charge_time(false);
+    let inner = process.inner_exclusive_access();
+    let usage = inner.usage();
+    let times = Tms {
+        utime: ticks_to_ms(usage.utime),
+        stime: ticks_to_ms(usage.stime),
+        cutime: ticks_to_ms(inner.children_usage.utime),
+        cstime: ticks_to_ms(inner.children_usage.stime),
+    };
+    drop(inner);
+    if !copy_out(tms, &times) {
+        return -1;
+    }
+    get_time_ms() as isize
+}
+
+/// Fill `usage` with that of this process for `RUSAGE_SELF`, of the children
+/// it waited for for `RUSAGE_CHILDREN` or of this thread for `RUSAGE_THREAD`.
+pub fn sys_getrusage(who: isize, usage: *mut Rusage) -> isize {
+    let task = current_task().unwrap();
+    let process = current_process();
+    task.inner_exclusive_access().charge_time(false);
+    let cpu_usage = match who {
+        RUSAGE_SELF => process.inner_exclusive_access().usage(),
+        RUSAGE_CHILDREN => process.inner_exclusive_access().children_usage,
+        RUSAGE_THREAD => task.inner_exclusive_access().usage,
+        _ => return -1,
+    };
+    if copy_out(usage, &Rusage::from(cpu_usage)) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Copy `value` to `dst` in the current process, which may cross pages.
+/// Return false if `dst` isn't writable.
+fn copy_out<T>(dst: *mut T, value: &T) -> bool {
+    let process = current_process();
+    let token = current_user_token();
+    if !process.inner_exclusive_access().memory_set.fault_in(
+        dst as usize,
+        core::mem::size_of::<T>(),
+        true,
+    ) {
+        return false;
+    }
+    let bytes = unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    copy_to_user(token, dst as *mut u8, bytes);
+    true
+}
+
 pub fn sys_getpid() -> isize {
     current_task().unwrap().process.upgrade().unwrap().getpid() as isize
 }
@@ -43,23 +119,45 @@ pub fn sys_fork() -> isize {
 
 pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     let token = current_user_token();
+    let process = current_process();
+    if !process
+        .inner_exclusive_access()
+        .memory_set
+        .fault_in_str(path as usize)
+    {
+        return -1;
+    }
     let path = translated_str(token, path);
     let mut args_vec: Vec<String> = Vec::new();
     loop {
+        let mut inner = process.inner_exclusive_access();
+        if !inner
+            .memory_set
+            .fault_in(args as usize, core::mem::size_of::<usize>(), false)
+        {
+            return -1;
+        }
         let arg_str_ptr = *translated_ref(token, args);
         if arg_str_ptr == 0 {
             break;
         }
+        if !inner.memory_set.fault_in_str(arg_str_ptr) {
+            return -1;
+        }
+        drop(inner);
         args_vec.push(translated_str(token, arg_str_ptr as *const u8));
         unsafe {
             args = args.add(1);
         }
     }
+    if ProcessControlBlock::args_size(&args_vec) > USER_STACK_SIZE {
+        return -1;
+    }
+    let path = normalize_path(&process.inner_exclusive_access().cwd, &path);
     if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
         let all_data = app_inode.read_all();
-        let process = current_process();
         let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
+        process.exec(&all_data, args_vec);
         // return argc because cx.x[10] will be covered with it later
         argc as isize
     } else {
@@ -88,12 +186,21 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ release child PCB
     });
     if let Some((idx, _)) = pair {
+        // keep the zombie if its exit code can't be stored
+        if !inner
+            .memory_set
+            .fault_in(exit_code_ptr as usize, core::mem::size_of::<i32>(), true)
+        {
+            return -1;
+        }
+        // the child is deallocated once the hart it exited on lets go of it
         let child = inner.children.remove(idx);
-        // confirm that child will be deallocated after being removed from children list
-        assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
+        let child_inner = child.inner_exclusive_access();
+        let exit_code = child_inner.exit_code;
+        inner.children_usage += child_inner.usage() + child_inner.children_usage;
+        drop(child_inner);
         // ++++ release child PCB
         *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
         found_pid as isize
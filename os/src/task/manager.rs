@@ -1,33 +1,37 @@
+use super::sched::{Policy, SchedPolicy};
 use super::{ProcessControlBlock, TaskControlBlock, TaskStatus};
-use crate::sync::UPIntrFreeCell;
-use alloc::collections::{BTreeMap, VecDeque};
+use crate::sync::IntrSpinLock;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use lazy_static::*;
 
 pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+    policy: Policy,
 }
 
-/// A simple FIFO scheduler.
+/// Ready threads, in the order the scheduling policy built in runs them.
 impl TaskManager {
     pub fn new() -> Self {
         Self {
-            ready_queue: VecDeque::new(),
+            policy: Policy::default(),
         }
     }
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
+        self.policy.add(task);
     }
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
+        self.policy.fetch()
+    }
+    pub fn preempted(&mut self, task: &TaskControlBlock) {
+        self.policy.preempted(task);
     }
 }
 
 lazy_static! {
-    pub static ref TASK_MANAGER: UPIntrFreeCell<TaskManager> =
-        unsafe { UPIntrFreeCell::new(TaskManager::new()) };
-    pub static ref PID2PCB: UPIntrFreeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
-        unsafe { UPIntrFreeCell::new(BTreeMap::new()) };
+    pub static ref TASK_MANAGER: IntrSpinLock<TaskManager> =
+        unsafe { IntrSpinLock::new(TaskManager::new()) };
+    pub static ref PID2PCB: IntrSpinLock<BTreeMap<usize, Arc<ProcessControlBlock>>> =
+        unsafe { IntrSpinLock::new(BTreeMap::new()) };
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
@@ -45,6 +49,20 @@ pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
     TASK_MANAGER.exclusive_access().fetch()
 }
 
+pub fn preempted_task(task: &TaskControlBlock) {
+    TASK_MANAGER.exclusive_access().preempted(task);
+}
+
+/// Call `f` on every thread of every process, no process may be borrowed.
+#[cfg(feature = "sched-mlfq")]
+pub fn for_each_task(mut f: impl FnMut(&TaskControlBlock)) {
+    for process in PID2PCB.exclusive_access().values() {
+        for task in process.inner_exclusive_access().tasks.iter().flatten() {
+            f(task);
+        }
+    }
+}
+
 pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
     let map = PID2PCB.exclusive_access();
     map.get(&pid).map(Arc::clone)
@@ -3,18 +3,23 @@ mod id;
 mod manager;
 mod process;
 mod processor;
+mod sched;
 mod signal;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
+mod usage;
 
 use self::id::TaskUserRes;
 use crate::fs::{open_file, OpenFlags};
+use crate::hart::online_harts;
 use crate::sbi::shutdown;
+use crate::timer::get_time_ms;
 use alloc::{sync::Arc, vec::Vec};
 use lazy_static::*;
-use manager::fetch_task;
-use process::ProcessControlBlock;
+use manager::{fetch_task, preempted_task};
+pub use process::ProcessControlBlock;
+use processor::idle_time_ms;
 use switch::__switch;
 
 pub use context::TaskContext;
@@ -24,15 +29,34 @@ pub use processor::{
     current_kstack_top, current_process, current_task, current_trap_cx, current_trap_cx_user_va,
     current_user_token, run_tasks, schedule, take_current_task,
 };
+pub use sched::{MAX_PRIORITY, MIN_PRIORITY};
 pub use signal::SignalFlags;
 pub use task::{TaskControlBlock, TaskStatus};
+pub use usage::{CpuUsage, Rusage, Tms};
 
 pub fn suspend_current_and_run_next() {
+    requeue_current_and_run_next(true);
+}
+
+/// The timer interrupt ends the slice of the running thread.
+pub fn preempt_current_and_run_next() {
+    preempted_task(&current_task().unwrap());
+    requeue_current_and_run_next(false);
+}
+
+/// Put the current thread back to the ready queue and run the next one,
+/// `voluntary` is false if the thread did not give up the hart itself.
+fn requeue_current_and_run_next(voluntary: bool) {
     // There must be an application running.
     let task = take_current_task().unwrap();
 
     // ---- access current TCB exclusively
     let mut task_inner = task.inner_exclusive_access();
+    if voluntary {
+        task_inner.usage.nvcsw += 1;
+    } else {
+        task_inner.usage.nivcsw += 1;
+    }
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
     // Change status to Ready
     task_inner.task_status = TaskStatus::Ready;
@@ -50,6 +74,7 @@ pub fn block_current_task() -> *mut TaskContext {
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
     task_inner.task_status = TaskStatus::Blocked;
+    task_inner.usage.nvcsw += 1;
     &mut task_inner.task_cx as *mut TaskContext
 }
 
@@ -67,10 +92,14 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     // record exit code
     task_inner.exit_code = Some(exit_code);
     task_inner.res = None;
+    task_inner.charge_time(false);
+    let usage = core::mem::take(&mut task_inner.usage);
     // here we do not remove the thread since we are still using the kstack
     // it will be deallocated when sys_waittid is called
     drop(task_inner);
     drop(task);
+    // the process keeps the usage of its exited threads
+    process.inner_exclusive_access().usage += usage;
     // however, if this is the main thread of current process
     // the process should terminate at once
     if tid == 0 {
@@ -80,6 +109,14 @@ pub fn exit_current_and_run_next(exit_code: i32) {
                 "[kernel] Idle process exit with exit_code {} ...",
                 exit_code
             );
+            for hart in online_harts() {
+                println!(
+                    "[kernel] hart {} idle for {} of {} ms",
+                    hart,
+                    idle_time_ms(hart),
+                    get_time_ms()
+                );
+            }
             if exit_code != 0 {
                 //crate::sbi::shutdown(255); //255 == -1 for err hint
                 shutdown(true);
@@ -94,35 +131,40 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         process_inner.is_zombie = true;
         // record exit code of main process
         process_inner.exit_code = exit_code;
-
-        {
-            // move all child processes under init process
-            let mut initproc_inner = INITPROC.inner_exclusive_access();
-            for child in process_inner.children.iter() {
-                child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
-                initproc_inner.children.push(child.clone());
-            }
-        }
+        // handed to the init process once this one is released, which
+        // the init process may be waiting on while holding itself
+        let children = core::mem::take(&mut process_inner.children);
 
         // deallocate user res (including tid/trap_cx/ustack) of all threads
         // it has to be done before we dealloc the whole memory_set
         // otherwise they will be deallocated twice
         let mut recycle_res = Vec::<TaskUserRes>::new();
+        let mut usage = CpuUsage::default();
         for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
             let task = task.as_ref().unwrap();
             let mut task_inner = task.inner_exclusive_access();
             if let Some(res) = task_inner.res.take() {
                 recycle_res.push(res);
             }
+            usage += core::mem::take(&mut task_inner.usage);
         }
+        process_inner.usage += usage;
         // dealloc_tid and dealloc_user_res require access to PCB inner, so we
         // need to collect those user res first, then release process_inner
         // for now to avoid deadlock/double borrow problem.
         drop(process_inner);
         recycle_res.clear();
 
+        {
+            // move all child processes under init process
+            let mut initproc_inner = INITPROC.inner_exclusive_access();
+            for child in children {
+                child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+                initproc_inner.children.push(child);
+            }
+        }
+
         let mut process_inner = process.inner_exclusive_access();
-        process_inner.children.clear();
         // deallocate other data in user space i.e. program code/data section
         process_inner.memory_set.recycle_data_pages();
         // drop file descriptors
@@ -145,7 +187,7 @@ lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
         let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
         let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        ProcessControlBlock::new(&v)
     };
 }
 
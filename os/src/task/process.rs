@@ -1,11 +1,12 @@
 use super::id::RecycleAllocator;
 use super::manager::insert_into_pid2process;
 use super::TaskControlBlock;
-use super::{add_task, SignalFlags};
+use super::{add_task, current_task, CpuUsage, SignalFlags};
 use super::{pid_alloc, PidHandle};
+use crate::config::USER_STACK_BASE;
 use crate::fs::{File, Stdin, Stdout};
 use crate::mm::{translated_refmut, MemorySet, KERNEL_SPACE};
-use crate::sync::{Condvar, Mutex, Semaphore, UPIntrFreeCell, UPIntrRefMut};
+use crate::sync::{Condvar, IntrSpinGuard, IntrSpinLock, Mutex, Semaphore};
 use crate::trap::{trap_handler, TrapContext};
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
@@ -16,7 +17,7 @@ pub struct ProcessControlBlock {
     // immutable
     pub pid: PidHandle,
     // mutable
-    inner: UPIntrFreeCell<ProcessControlBlockInner>,
+    inner: IntrSpinLock<ProcessControlBlockInner>,
 }
 
 pub struct ProcessControlBlockInner {
@@ -32,9 +33,22 @@ pub struct ProcessControlBlockInner {
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// Normalized absolute path of the working directory.
+    pub cwd: String,
+    /// Usage of the threads exited.
+    pub usage: CpuUsage,
+    /// Usage of the children waited for, including that of their children.
+    pub children_usage: CpuUsage,
 }
 
 impl ProcessControlBlockInner {
+    /// Usage of all threads, exited or not.
+    pub fn usage(&self) -> CpuUsage {
+        self.tasks.iter().flatten().fold(self.usage, |usage, task| {
+            usage + task.inner_exclusive_access().usage
+        })
+    }
+
     #[allow(unused)]
     pub fn get_user_token(&self) -> usize {
         self.memory_set.token()
@@ -67,19 +81,19 @@ impl ProcessControlBlockInner {
 }
 
 impl ProcessControlBlock {
-    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, ProcessControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> IntrSpinGuard<'_, ProcessControlBlockInner> {
         self.inner.exclusive_access()
     }
 
     pub fn new(elf_data: &[u8]) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, entry_point) = MemorySet::from_elf(elf_data);
         // allocate a pid
         let pid_handle = pid_alloc();
         let process = Arc::new(Self {
             pid: pid_handle,
             inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
+                IntrSpinLock::new(ProcessControlBlockInner {
                     is_zombie: false,
                     memory_set,
                     parent: None,
@@ -99,13 +113,16 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    cwd: String::from("/"),
+                    usage: CpuUsage::default(),
+                    children_usage: CpuUsage::default(),
                 })
             },
         });
         // create a main thread, we should allocate ustack and trap_cx here
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&process),
-            ustack_base,
+            USER_STACK_BASE,
             true,
         ));
         // prepare trap_cx of main thread
@@ -131,11 +148,18 @@ impl ProcessControlBlock {
         process
     }
 
+    /// Bytes `exec` pushes on the user stack for `args`, `argv` and the alignment.
+    pub fn args_size(args: &[String]) -> usize {
+        (args.len() + 1) * core::mem::size_of::<usize>()
+            + args.iter().map(|arg| arg.len() + 1).sum::<usize>()
+            + core::mem::size_of::<usize>()
+    }
+
     /// Only support processes with a single thread.
     pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, entry_point) = MemorySet::from_elf(elf_data);
         let new_token = memory_set.token();
         // substitute memory_set
         self.inner_exclusive_access().memory_set = memory_set;
@@ -143,11 +167,17 @@ impl ProcessControlBlock {
         // since memory_set has been changed
         let task = self.inner_exclusive_access().get_task(0);
         let mut task_inner = task.inner_exclusive_access();
-        task_inner.res.as_mut().unwrap().ustack_base = ustack_base;
         task_inner.res.as_mut().unwrap().alloc_user_res();
         task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
         // push arguments on user stack
         let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
+        let args_size = Self::args_size(&args);
+        assert!(
+            self.inner_exclusive_access()
+                .memory_set
+                .fault_in(user_sp - args_size, args_size, true),
+            "arguments don't fit in the user stack"
+        );
         user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
         let argv_base = user_sp;
         let mut argv: Vec<_> = (0..=args.len())
@@ -188,8 +218,9 @@ impl ProcessControlBlock {
     pub fn fork(self: &Arc<Self>) -> Arc<Self> {
         let mut parent = self.inner_exclusive_access();
         assert_eq!(parent.thread_count(), 1);
-        // clone parent's memory_set completely including trampoline/ustacks/trap_cxs
-        let memory_set = MemorySet::from_existed_user(&parent.memory_set);
+        // clone parent's memory_set including trampoline/ustacks/trap_cxs,
+        // user pages are shared copy-on-write
+        let memory_set = MemorySet::from_existed_user(&mut parent.memory_set);
         // alloc a pid
         let pid = pid_alloc();
         // copy fd table
@@ -205,7 +236,7 @@ impl ProcessControlBlock {
         let child = Arc::new(Self {
             pid,
             inner: unsafe {
-                UPIntrFreeCell::new(ProcessControlBlockInner {
+                IntrSpinLock::new(ProcessControlBlockInner {
                     is_zombie: false,
                     memory_set,
                     parent: Some(Arc::downgrade(self)),
@@ -218,6 +249,9 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    cwd: parent.cwd.clone(),
+                    usage: CpuUsage::default(),
+                    children_usage: CpuUsage::default(),
                 })
             },
         });
@@ -237,6 +271,7 @@ impl ProcessControlBlock {
             // but mention that we allocate a new kstack here
             false,
         ));
+        task.inherit_sched(&current_task().unwrap());
         // attach task to child process
         let mut child_inner = child.inner_exclusive_access();
         child_inner.tasks.push(Some(Arc::clone(&task)));
@@ -246,6 +281,8 @@ impl ProcessControlBlock {
         let trap_cx = task_inner.get_trap_cx();
         trap_cx.kernel_sp = task.kstack.get_top();
         drop(task_inner);
+        // the scheduler may lock the processes while holding the tables below
+        drop(parent);
         insert_into_pid2process(child.getpid(), Arc::clone(&child));
         // add this thread to scheduler
         add_task(task);
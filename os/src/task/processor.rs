@@ -1,15 +1,24 @@
 use super::__switch;
 use super::{fetch_task, TaskStatus};
 use super::{ProcessControlBlock, TaskContext, TaskControlBlock};
-use crate::sync::UPIntrFreeCell;
+use crate::config::MAX_HARTS;
+use crate::hart::{self, hart_id};
+use crate::sync::IntrSpinLock;
+use crate::timer::{get_time, ticks_to_ms};
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::arch::asm;
+use core::hint::spin_loop;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
+use riscv::register::sstatus;
 
 pub struct Processor {
     current: Option<Arc<TaskControlBlock>>,
     idle_task_cx: TaskContext,
+    /// Time spent waiting for a ready thread, in ticks of `get_time`.
+    idle_ticks: usize,
 }
 
 impl Processor {
@@ -17,6 +26,7 @@ impl Processor {
         Self {
             current: None,
             idle_task_cx: TaskContext::zero_init(),
+            idle_ticks: 0,
         }
     }
     fn get_idle_task_cx_ptr(&mut self) -> *mut TaskContext {
@@ -31,38 +41,77 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPIntrFreeCell<Processor> =
-        unsafe { UPIntrFreeCell::new(Processor::new()) };
+    /// One processor for each hart, indexed by hart ID.
+    static ref PROCESSORS: Vec<IntrSpinLock<Processor>> = (0..MAX_HARTS)
+        .map(|_| unsafe { IntrSpinLock::new(Processor::new()) })
+        .collect();
+}
+
+/// The processor of the hart this runs on.
+fn processor() -> &'static IntrSpinLock<Processor> {
+    &PROCESSORS[hart_id()]
 }
 
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
+        // interrupts are only taken in `idle`, so none can make a thread
+        // ready between finding the queue empty and `wfi`
+        unsafe {
+            sstatus::clear_sie();
+        }
         if let Some(task) = fetch_task() {
+            // the hart which put it back may not have switched away from it yet
+            while task.on_hart.load(Ordering::Acquire) {
+                spin_loop();
+            }
+            task.on_hart.store(true, Ordering::Relaxed);
+            let mut processor = processor().exclusive_access();
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
             // access coming task TCB exclusively
             let next_task_cx_ptr = task.inner.exclusive_session(|task_inner| {
                 task_inner.task_status = TaskStatus::Running;
+                task_inner.last_charged = get_time();
                 &task_inner.task_cx as *const TaskContext
             });
-            processor.current = Some(task);
+            processor.current = Some(Arc::clone(&task));
             // release processor manually
             drop(processor);
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            task.inner_exclusive_access().charge_time(false);
+            // its context is saved, another hart may run it now
+            task.on_hart.store(false, Ordering::Release);
         } else {
-            println!("no tasks available in run_tasks");
+            idle();
         }
     }
 }
 
+/// Sleep until an interrupt, which may make a thread ready. A thread made
+/// ready by another hart is found after the next timer interrupt at the latest.
+fn idle() {
+    let start = get_time();
+    unsafe {
+        // an interrupt pending ends `wfi` even while masked, take it now
+        asm!("wfi");
+        sstatus::set_sie();
+        sstatus::clear_sie();
+    }
+    processor().exclusive_access().idle_ticks += get_time() - start;
+}
+
+/// Time hart `hart` has spent idle so far, in milliseconds.
+pub fn idle_time_ms(hart: usize) -> usize {
+    ticks_to_ms(PROCESSORS[hart].exclusive_access().idle_ticks)
+}
+
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+    processor().exclusive_access().take_current()
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    processor().exclusive_access().current()
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -95,16 +144,14 @@ pub fn current_kstack_top() -> usize {
     if let Some(task) = current_task() {
         task.kstack.get_top()
     } else {
-        let mut boot_stack_top;
-        unsafe { asm!("la {},boot_stack_top",out(reg) boot_stack_top) };
-        boot_stack_top
+        hart::boot_stack_top()
     }
     // current_task().unwrap().kstack.get_top()
 }
 
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
     let idle_task_cx_ptr =
-        PROCESSOR.exclusive_session(|processor| processor.get_idle_task_cx_ptr());
+        processor().exclusive_session(|processor| processor.get_idle_task_cx_ptr());
     unsafe {
         __switch(switched_task_cx_ptr, idle_task_cx_ptr);
     }
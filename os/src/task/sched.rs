@@ -0,0 +1,186 @@
+use super::TaskControlBlock;
+#[cfg(any(feature = "sched-stride", feature = "sched-priority"))]
+use alloc::collections::BTreeMap;
+#[cfg(not(feature = "sched-stride"))]
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+#[cfg(feature = "sched-mlfq")]
+use super::manager::for_each_task;
+#[cfg(feature = "sched-mlfq")]
+use crate::timer::get_time_ms;
+
+#[cfg(any(
+    all(feature = "sched-stride", feature = "sched-priority"),
+    all(feature = "sched-stride", feature = "sched-mlfq"),
+    all(feature = "sched-priority", feature = "sched-mlfq")
+))]
+compile_error!("only one scheduling policy can be enabled");
+
+/// How `TaskManager` picks the next ready thread, chosen at build time with the
+/// `sched-stride`, `sched-priority` or `sched-mlfq` feature, first come first served by default.
+pub trait SchedPolicy: Default {
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    /// The timer interrupt ended the slice of `task`, which is added back next.
+    fn preempted(&mut self, _task: &TaskControlBlock) {}
+}
+
+#[cfg(feature = "sched-stride")]
+pub type Policy = StridePolicy;
+#[cfg(feature = "sched-priority")]
+pub type Policy = PriorityPolicy;
+#[cfg(feature = "sched-mlfq")]
+pub type Policy = MlfqPolicy;
+#[cfg(not(any(
+    feature = "sched-stride",
+    feature = "sched-priority",
+    feature = "sched-mlfq"
+)))]
+pub type Policy = FifoPolicy;
+
+/// Priority of a thread not set with `sys_set_priority`.
+pub const DEFAULT_PRIORITY: usize = 16;
+/// Lower priorities would let the passes of ready threads drift too far apart.
+pub const MIN_PRIORITY: usize = 2;
+/// Higher priorities would give a thread a stride of 0 under stride scheduling.
+pub const MAX_PRIORITY: usize = 1 << 20;
+
+#[cfg(not(any(
+    feature = "sched-stride",
+    feature = "sched-priority",
+    feature = "sched-mlfq"
+)))]
+#[derive(Default)]
+pub struct FifoPolicy {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+#[cfg(not(any(
+    feature = "sched-stride",
+    feature = "sched-priority",
+    feature = "sched-mlfq"
+)))]
+impl SchedPolicy for FifoPolicy {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.pop_front()
+    }
+}
+
+/// The pass of a thread grows by `BIG_STRIDE / priority` each time it runs.
+#[cfg(feature = "sched-stride")]
+const BIG_STRIDE: u64 = MAX_PRIORITY as u64;
+
+/// Stride scheduling, the thread with the smallest pass runs next,
+/// so each gets slices in proportion to its priority.
+#[cfg(feature = "sched-stride")]
+#[derive(Default)]
+pub struct StridePolicy {
+    /// Ready threads by pass, then by order of arrival.
+    ready_queue: BTreeMap<(u64, u64), Arc<TaskControlBlock>>,
+    arrivals: u64,
+    /// Pass of the thread fetched last, a thread back from sleeping starts here
+    /// rather than running until it catches up.
+    current_pass: u64,
+}
+
+#[cfg(feature = "sched-stride")]
+impl SchedPolicy for StridePolicy {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.pass = task_inner.pass.max(self.current_pass);
+        let key = (task_inner.pass, self.arrivals);
+        drop(task_inner);
+        self.arrivals += 1;
+        self.ready_queue.insert(key, task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let ((pass, _), task) = self.ready_queue.pop_first()?;
+        self.current_pass = pass;
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.pass += BIG_STRIDE / task_inner.priority as u64;
+        drop(task_inner);
+        Some(task)
+    }
+}
+
+/// Static priorities, a ready thread runs only when none of a higher priority is
+/// ready and threads of the same priority take turns.
+#[cfg(feature = "sched-priority")]
+#[derive(Default)]
+pub struct PriorityPolicy {
+    ready_queues: BTreeMap<usize, VecDeque<Arc<TaskControlBlock>>>,
+}
+
+#[cfg(feature = "sched-priority")]
+impl SchedPolicy for PriorityPolicy {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let priority = task.inner_exclusive_access().priority;
+        self.ready_queues
+            .entry(priority)
+            .or_default()
+            .push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let mut highest = self.ready_queues.last_entry()?;
+        let task = highest.get_mut().pop_front();
+        if highest.get().is_empty() {
+            highest.remove();
+        }
+        task
+    }
+}
+
+/// Levels of the multi-level feedback queue, threads of level 0 run first.
+#[cfg(feature = "sched-mlfq")]
+const MLFQ_LEVELS: usize = 4;
+/// Every thread is put back to level 0 this often, so that none starves.
+#[cfg(feature = "sched-mlfq")]
+const BOOST_INTERVAL_MS: usize = 1000;
+
+/// A multi-level feedback queue. A thread using up its slice drops a level, one
+/// blocking or yielding before that keeps its level, so interactive threads stay
+/// ahead of CPU hogs. Threads of the same level take turns.
+#[cfg(feature = "sched-mlfq")]
+#[derive(Default)]
+pub struct MlfqPolicy {
+    ready_queues: [VecDeque<Arc<TaskControlBlock>>; MLFQ_LEVELS],
+    last_boost_ms: usize,
+}
+
+#[cfg(feature = "sched-mlfq")]
+impl MlfqPolicy {
+    /// Put every thread, ready or not, back to level 0.
+    fn boost(&mut self) {
+        for_each_task(|task| task.inner_exclusive_access().level = 0);
+        let (top, lower) = self.ready_queues.split_first_mut().unwrap();
+        for queue in lower {
+            top.extend(queue.drain(..));
+        }
+    }
+}
+
+#[cfg(feature = "sched-mlfq")]
+impl SchedPolicy for MlfqPolicy {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let level = task.inner_exclusive_access().level;
+        self.ready_queues[level].push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let now = get_time_ms();
+        if now - self.last_boost_ms >= BOOST_INTERVAL_MS {
+            self.boost();
+            self.last_boost_ms = now;
+        }
+        self.ready_queues
+            .iter_mut()
+            .find_map(|queue| queue.pop_front())
+    }
+    fn preempted(&mut self, task: &TaskControlBlock) {
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.level = (task_inner.level + 1).min(MLFQ_LEVELS - 1);
+    }
+}
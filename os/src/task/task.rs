@@ -1,22 +1,29 @@
 use super::id::TaskUserRes;
+use super::sched::DEFAULT_PRIORITY;
+use super::CpuUsage;
 use super::{kstack_alloc, KernelStack, ProcessControlBlock, TaskContext};
+use crate::timer::get_time;
 use crate::trap::TrapContext;
 use crate::{
     mm::PhysPageNum,
-    sync::{UPIntrFreeCell, UPIntrRefMut},
+    sync::{IntrSpinGuard, IntrSpinLock},
 };
 use alloc::sync::{Arc, Weak};
+use core::sync::atomic::AtomicBool;
 
 pub struct TaskControlBlock {
     // immutable
     pub process: Weak<ProcessControlBlock>,
     pub kstack: KernelStack,
+    /// Set while a hart runs on `kstack`, a thread added back to the ready queue
+    /// may still be switching away from it.
+    pub on_hart: AtomicBool,
     // mutable
-    pub inner: UPIntrFreeCell<TaskControlBlockInner>,
+    pub inner: IntrSpinLock<TaskControlBlockInner>,
 }
 
 impl TaskControlBlock {
-    pub fn inner_exclusive_access(&self) -> UPIntrRefMut<'_, TaskControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> IntrSpinGuard<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
 
@@ -25,6 +32,15 @@ impl TaskControlBlock {
         let inner = process.inner_exclusive_access();
         inner.memory_set.token()
     }
+
+    /// Start from the scheduling state of `parent`, the thread creating this one.
+    pub fn inherit_sched(&self, parent: &TaskControlBlock) {
+        let parent_inner = parent.inner_exclusive_access();
+        let mut inner = self.inner_exclusive_access();
+        inner.priority = parent_inner.priority;
+        inner.pass = parent_inner.pass;
+        inner.level = parent_inner.level;
+    }
 }
 
 pub struct TaskControlBlockInner {
@@ -33,6 +49,16 @@ pub struct TaskControlBlockInner {
     pub task_cx: TaskContext,
     pub task_status: TaskStatus,
     pub exit_code: Option<i32>,
+    /// Set with `sys_set_priority`, how the policy uses it depends on the policy.
+    pub priority: usize,
+    /// Pass value of stride scheduling.
+    pub pass: u64,
+    /// Queue of the multi-level feedback queue, 0 is the highest.
+    pub level: usize,
+    /// Moved to the process when the thread exits.
+    pub usage: CpuUsage,
+    /// When the time of this thread was last charged to `usage`.
+    pub last_charged: usize,
 }
 
 impl TaskControlBlockInner {
@@ -40,6 +66,17 @@ impl TaskControlBlockInner {
         self.trap_cx_ppn.get_mut()
     }
 
+    /// Charge the time since `last_charged` to the user or system time of this thread.
+    pub fn charge_time(&mut self, user: bool) {
+        let now = get_time();
+        if user {
+            self.usage.utime += now - self.last_charged;
+        } else {
+            self.usage.stime += now - self.last_charged;
+        }
+        self.last_charged = now;
+    }
+
     #[allow(unused)]
     fn get_status(&self) -> TaskStatus {
         self.task_status
@@ -59,13 +96,19 @@ impl TaskControlBlock {
         Self {
             process: Arc::downgrade(&process),
             kstack,
+            on_hart: AtomicBool::new(false),
             inner: unsafe {
-                UPIntrFreeCell::new(TaskControlBlockInner {
+                IntrSpinLock::new(TaskControlBlockInner {
                     res: Some(res),
                     trap_cx_ppn,
                     task_cx: TaskContext::goto_trap_return(kstack_top),
                     task_status: TaskStatus::Ready,
                     exit_code: None,
+                    priority: DEFAULT_PRIORITY,
+                    pass: 0,
+                    level: 0,
+                    usage: CpuUsage::default(),
+                    last_charged: 0,
                 })
             },
         }
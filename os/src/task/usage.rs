@@ -0,0 +1,70 @@
+use crate::timer::ticks_to_ms;
+use core::ops::{Add, AddAssign};
+
+/// CPU time and context switches of a thread, or the sum of those of several.
+#[derive(Clone, Copy, Default)]
+pub struct CpuUsage {
+    /// Time in user mode, in ticks of `get_time`.
+    pub utime: usize,
+    /// Time in the kernel on behalf of the thread, in ticks of `get_time`.
+    pub stime: usize,
+    /// Times the thread gave up its hart, by yielding or blocking.
+    pub nvcsw: usize,
+    /// Times the timer interrupt took the hart away.
+    pub nivcsw: usize,
+}
+
+impl Add for CpuUsage {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Self {
+            utime: self.utime + other.utime,
+            stime: self.stime + other.stime,
+            nvcsw: self.nvcsw + other.nvcsw,
+            nivcsw: self.nivcsw + other.nivcsw,
+        }
+    }
+}
+
+impl AddAssign for CpuUsage {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl From<CpuUsage> for Rusage {
+    fn from(usage: CpuUsage) -> Self {
+        Self {
+            utime: ticks_to_ms(usage.utime),
+            stime: ticks_to_ms(usage.stime),
+            nvcsw: usage.nvcsw,
+            nivcsw: usage.nivcsw,
+        }
+    }
+}
+
+/// Process times returned by `sys_times`, in ms.
+#[repr(C)]
+pub struct Tms {
+    /// user time of all threads
+    pub utime: usize,
+    /// system time of all threads
+    pub stime: usize,
+    /// user time of the children waited for and of theirs
+    pub cutime: usize,
+    /// system time of the children waited for and of theirs
+    pub cstime: usize,
+}
+
+/// Resource usage returned by `sys_getrusage`.
+#[repr(C)]
+pub struct Rusage {
+    /// user time in ms
+    pub utime: usize,
+    /// system time in ms
+    pub stime: usize,
+    /// voluntary context switches
+    pub nvcsw: usize,
+    /// involuntary context switches
+    pub nivcsw: usize,
+}
@@ -9,6 +9,8 @@ pub struct TrapContext {
     pub kernel_satp: usize,
     pub kernel_sp: usize,
     pub trap_handler: usize,
+    /// `tp` of the hart that returned to user space last, set by `__restore`.
+    pub kernel_tp: usize,
 }
 
 impl TrapContext {
@@ -32,6 +34,7 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             trap_handler,
+            kernel_tp: 0,
         };
         cx.set_sp(sp);
         cx
@@ -1,10 +1,12 @@
 mod context;
 
 use crate::config::TRAMPOLINE;
+use crate::mm::VirtAddr;
 use crate::syscall::syscall;
 use crate::task::{
-    check_signals_of_current, current_add_signal, current_trap_cx, current_trap_cx_user_va,
-    current_user_token, exit_current_and_run_next, suspend_current_and_run_next, SignalFlags,
+    check_signals_of_current, current_add_signal, current_process, current_trap_cx,
+    current_task, current_trap_cx_user_va, current_user_token, exit_current_and_run_next,
+    preempt_current_and_run_next, SignalFlags,
 };
 use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{asm, global_asm};
@@ -59,6 +61,10 @@ fn disable_supervisor_interrupt() {
 #[no_mangle]
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .charge_time(true);
     let scause = scause::read();
     let stval = stval::read();
     // println!("into {:?}", scause.cause());
@@ -71,11 +77,27 @@ pub fn trap_handler() -> ! {
             enable_supervisor_interrupt();
 
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault)
+            if current_process()
+                .inner_exclusive_access()
+                .memory_set
+                .handle_page_fault(
+                    VirtAddr::from(stval),
+                    scause.cause() == Trap::Exception(Exception::StorePageFault),
+                ) =>
+        {
+            // the page has been allocated, loaded or made private
+        }
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionFault)
@@ -98,7 +120,7 @@ pub fn trap_handler() -> ! {
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
             check_timer();
-            suspend_current_and_run_next();
+            preempt_current_and_run_next();
         }
         Trap::Interrupt(Interrupt::SupervisorExternal) => {
             crate::board::irq_handler();
@@ -122,6 +144,10 @@ pub fn trap_handler() -> ! {
 #[no_mangle]
 pub fn trap_return() -> ! {
     disable_supervisor_interrupt();
+    current_task()
+        .unwrap()
+        .inner_exclusive_access()
+        .charge_time(false);
     set_user_trap_entry();
     let trap_cx_user_va = current_trap_cx_user_va();
     let user_satp = current_user_token();
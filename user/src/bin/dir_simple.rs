@@ -0,0 +1,54 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{chdir, close, getcwd, mkdir, open, read, rmdir, unlink, write, OpenFlags};
+
+fn cwd(buf: &mut [u8]) -> &str {
+    let len = getcwd(buf);
+    assert!(len > 0);
+    core::str::from_utf8(&buf[..len as usize]).unwrap()
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 64];
+    assert_eq!(chdir("/\0"), 0);
+    assert_eq!(cwd(&mut buf), "/");
+    assert_eq!(mkdir("dir_simple_a\0"), 0);
+    assert_eq!(mkdir("dir_simple_a/b\0"), 0);
+    assert_eq!(mkdir("dir_simple_a\0"), -1);
+    assert_eq!(mkdir("no_such_dir/b\0"), -1);
+
+    assert_eq!(chdir("dir_simple_a/./b\0"), 0);
+    assert_eq!(cwd(&mut buf), "/dir_simple_a/b");
+    assert_eq!(getcwd(&mut buf[..4]), -1);
+
+    // relative paths start from the working directory
+    let test_str = "Hello, directory!";
+    let fd = open("file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+    assert_eq!(chdir("..\0"), 0);
+    assert_eq!(cwd(&mut buf), "/dir_simple_a");
+    assert_eq!(chdir("b/file\0"), -1);
+    let fd = open("../dir_simple_a/b/file\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 64];
+    let read_len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    assert_eq!(test_str.as_bytes(), &buffer[..read_len]);
+
+    // directories cannot be written
+    assert_eq!(open("b\0", OpenFlags::WRONLY), -1);
+    assert_eq!(rmdir("b\0"), -1);
+    assert_eq!(unlink("b/file\0"), 0);
+    assert_eq!(rmdir("b\0"), 0);
+    assert_eq!(chdir("/\0"), 0);
+    assert_eq!(rmdir("dir_simple_a\0"), 0);
+    println!("dir_simple passed!");
+    0
+}
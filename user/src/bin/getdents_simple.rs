@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{
+    close, getdents, lseek, mkdir, open, rmdir, unlink, DirentIter, OpenFlags, DT_DIR, DT_REG,
+    SEEK_SET,
+};
+
+const FILE_COUNT: usize = 20;
+
+/// Read all the records of `fd` with a `buf_len` bytes buffer.
+fn read_all(fd: usize, buf_len: usize) -> Vec<(String, u8)> {
+    let mut buf = [0u8; 512];
+    let mut names = Vec::new();
+    loop {
+        let len = getdents(fd, &mut buf[..buf_len]);
+        assert!(len >= 0);
+        if len == 0 {
+            return names;
+        }
+        for dirent in DirentIter::new(&buf[..len as usize]) {
+            names.push((String::from(dirent.name), dirent.type_));
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("/getdents_dir\0"), 0);
+    assert_eq!(mkdir("/getdents_dir/sub\0"), 0);
+    for i in 0..FILE_COUNT {
+        let path = format!("/getdents_dir/file{}\0", i);
+        close(open(path.as_str(), OpenFlags::CREATE | OpenFlags::WRONLY) as usize);
+    }
+    assert_eq!(unlink("/getdents_dir/file3\0"), 0);
+
+    let fd = open("/getdents_dir\0", OpenFlags::RDONLY) as usize;
+    // too small for a single record
+    let mut tiny = [0u8; 8];
+    assert_eq!(getdents(fd, &mut tiny), -1);
+    // small batches see every entry exactly once
+    let entries = read_all(fd, 64);
+    assert_eq!(entries.len(), FILE_COUNT + 2);
+    assert_eq!(entries[0], (String::from("."), DT_DIR));
+    assert_eq!(entries[1], (String::from(".."), DT_DIR));
+    assert_eq!(entries[2], (String::from("sub"), DT_DIR));
+    for i in 0..FILE_COUNT {
+        let present = entries.contains(&(format!("file{}", i), DT_REG));
+        assert_eq!(present, i != 3);
+    }
+    // rewinding lists the directory again
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    assert_eq!(read_all(fd, 512), entries);
+    close(fd);
+
+    // regular files are not directories
+    let fd = open("/getdents_dir/file0\0", OpenFlags::RDONLY) as usize;
+    assert_eq!(getdents(fd, &mut [0u8; 64]), -1);
+    close(fd);
+
+    for i in 0..FILE_COUNT {
+        unlink(format!("/getdents_dir/file{}\0", i).as_str());
+    }
+    assert_eq!(rmdir("/getdents_dir/sub\0"), 0);
+    assert_eq!(rmdir("/getdents_dir\0"), 0);
+    println!("getdents_simple passed!");
+    0
+}
@@ -5,7 +5,7 @@ extern crate alloc;
 extern crate user_lib;
 
 use user_lib::console::getchar;
-use user_lib::{key_pressed, sleep, Display, VIRTGPU_XRES, VIRTGPU_YRES};
+use user_lib::{key_pressed, set_priority, sleep, Display, VIRTGPU_XRES, VIRTGPU_YRES};
 
 use embedded_graphics::pixelcolor::*;
 use embedded_graphics::prelude::{Drawable, Point, RgbColor, Size};
@@ -327,6 +327,8 @@ const LF: u8 = 0x0au8;
 const CR: u8 = 0x0du8;
 #[no_mangle]
 pub fn main() -> i32 {
+    // keep the game responsive next to batch jobs
+    set_priority(64);
     let mut disp = Display::new(Size::new(VIRTGPU_XRES, VIRTGPU_YRES));
     let mut game = SnakeGame::<20, Rgb888>::new(1280, 800, 20, 20, Rgb888::RED, Rgb888::YELLOW, 200);
     let _ = disp.clear(Rgb888::BLACK).unwrap();
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, sbrk};
+
+const PAGE_SIZE: usize = 0x1000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // raw program break
+    let old_brk = brk(0);
+    assert!(old_brk > 0);
+    assert_eq!(sbrk(2 * PAGE_SIZE as isize), old_brk);
+    assert_eq!(brk(0), old_brk + 2 * PAGE_SIZE as isize);
+    let heap = unsafe { core::slice::from_raw_parts_mut(old_brk as *mut u8, 2 * PAGE_SIZE) };
+    assert!(heap.iter().all(|b| *b == 0));
+    heap.fill(0xa5);
+    assert_eq!(
+        sbrk(-(PAGE_SIZE as isize)),
+        old_brk + 2 * PAGE_SIZE as isize
+    );
+    assert_eq!(heap[PAGE_SIZE - 1], 0xa5);
+    // a failed brk leaves the break where it was
+    assert_eq!(
+        brk(old_brk as usize - PAGE_SIZE),
+        old_brk + PAGE_SIZE as isize
+    );
+
+    // far more than the initial heap of user_lib
+    let len = 1 << 17;
+    let mut v: Vec<usize> = Vec::with_capacity(len);
+    for i in 0..len {
+        v.push(i);
+    }
+    for (i, x) in v.iter().enumerate() {
+        assert_eq!(*x, i);
+    }
+    println!("heap_grow passed!");
+    0
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, link, mkdir, open, read, rename, rmdir, unlink, write, OpenFlags};
+
+fn read_file(path: &str, buf: &mut [u8]) -> isize {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let len = read(fd as usize, buf);
+    close(fd as usize);
+    len
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let test_str = "Hello, link!";
+    let fd = open("/link_a\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+
+    let mut buf = [0u8; 32];
+    assert_eq!(link("/link_a\0", "/link_b\0"), 0);
+    assert_eq!(link("/link_a\0", "/link_b\0"), -1);
+    assert_eq!(unlink("/link_a\0"), 0);
+    assert_eq!(read_file("/link_a\0", &mut buf), -1);
+    let len = read_file("/link_b\0", &mut buf) as usize;
+    assert_eq!(&buf[..len], test_str.as_bytes());
+
+    assert_eq!(mkdir("/link_dir\0"), 0);
+    assert_eq!(link("/link_dir\0", "/link_dir2\0"), -1);
+    assert_eq!(unlink("/link_dir\0"), -1);
+    assert_eq!(rename("/link_b\0", "/link_dir/link_c\0"), 0);
+    assert_eq!(read_file("/link_b\0", &mut buf), -1);
+    let len = read_file("/link_dir/link_c\0", &mut buf) as usize;
+    assert_eq!(&buf[..len], test_str.as_bytes());
+    assert_eq!(rename("/link_dir\0", "/link_dir/sub\0"), -1);
+    assert_eq!(rmdir("/link_dir\0"), -1);
+    assert_eq!(unlink("/link_dir/link_c\0"), 0);
+    assert_eq!(rmdir("/link_dir\0"), 0);
+    println!("link_simple passed!");
+    0
+}
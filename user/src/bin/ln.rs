@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::link;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 3 {
+        println!("usage: ln <target> <link_name>");
+        return -1;
+    }
+    // the arguments are followed by '\0' in memory
+    if link(argv[1], argv[2]) == -1 {
+        println!("ln: cannot link {} to {}", argv[2], argv[1]);
+        return -1;
+    }
+    0
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, getdents, open, stat, DirentIter, OpenFlags, Stat, DT_DIR};
+
+/// List the directory `path`, names starting with '.' are hidden unless `all`.
+fn list_dir(path: &str, all: bool) -> i32 {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd == -1 {
+        println!("ls: cannot open {}", path);
+        return -1;
+    }
+    let fd = fd as usize;
+    let mut buf = [0u8; 512];
+    loop {
+        let len = getdents(fd, &mut buf);
+        if len <= 0 {
+            close(fd);
+            return len as i32;
+        }
+        for dirent in DirentIter::new(&buf[..len as usize]) {
+            if dirent.name.starts_with('.') && !all {
+                continue;
+            }
+            if dirent.type_ == DT_DIR {
+                println!("{}/", dirent.name);
+            } else {
+                println!("{}", dirent.name);
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let all = argc > 1 && argv[1] == "-a";
+    let paths = if all { &argv[2..] } else { &argv[1..] };
+    if paths.is_empty() {
+        return list_dir(".\0", all);
+    }
+    let mut exit_code = 0;
+    // the arguments are followed by '\0' in memory
+    for (i, path) in paths.iter().enumerate() {
+        let mut st = Stat::default();
+        if stat(path, &mut st) == -1 {
+            println!("ls: {}: no such file or directory", path);
+            exit_code = -1;
+            continue;
+        }
+        if !st.is_dir() {
+            println!("{}", path);
+            continue;
+        }
+        if paths.len() > 1 {
+            let sep = if i > 0 { "\n" } else { "" };
+            println!("{}{}:", sep, path);
+        }
+        if list_dir(path, all) != 0 {
+            exit_code = -1;
+        }
+    }
+    exit_code
+}
@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::mkdir;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: mkdir <dir>...");
+        return -1;
+    }
+    let mut exit_code = 0;
+    // the arguments are followed by '\0' in memory
+    for dir in argv[1..].iter() {
+        if mkdir(dir) == -1 {
+            println!("mkdir: cannot create directory {}", dir);
+            exit_code = -1;
+        }
+    }
+    exit_code
+}
@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fork, mmap, mprotect, munmap, open, waitpid, write, MapFlags, OpenFlags, ProtFlags,
+};
+
+const PAGE_SIZE: usize = 0x1000;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // anonymous mapping, zero filled
+    let len = 4 * PAGE_SIZE;
+    let addr = mmap(
+        0,
+        len,
+        ProtFlags::READ | ProtFlags::WRITE,
+        MapFlags::PRIVATE | MapFlags::ANONYMOUS,
+        0,
+        0,
+    );
+    assert!(addr > 0);
+    let addr = addr as usize;
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) };
+    assert!(buf.iter().all(|b| *b == 0));
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+
+    // a second mapping must not overlap the first one
+    let other = mmap(
+        addr,
+        PAGE_SIZE,
+        ProtFlags::READ,
+        MapFlags::PRIVATE | MapFlags::ANONYMOUS,
+        0,
+        0,
+    );
+    assert!(other > 0 && other as usize != addr);
+    assert_eq!(munmap(other as usize, PAGE_SIZE), 0);
+
+    // punch a hole in the middle, the rest stays usable
+    assert_eq!(munmap(addr + PAGE_SIZE, PAGE_SIZE), 0);
+    assert_eq!(buf[0], 0);
+    assert_eq!(buf[2 * PAGE_SIZE + 1], 1);
+    assert_eq!(mprotect(addr + PAGE_SIZE, PAGE_SIZE, ProtFlags::READ), -1);
+
+    // a read-only page kills the process on write
+    assert_eq!(mprotect(addr, PAGE_SIZE, ProtFlags::READ), 0);
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            (addr as *mut u8).write_volatile(0);
+        }
+        panic!("Should not reach here!");
+    }
+    let mut exit_code: i32 = 0;
+    waitpid(pid as usize, &mut exit_code);
+    assert_eq!(exit_code, -11);
+    assert_eq!(
+        mprotect(addr, PAGE_SIZE, ProtFlags::READ | ProtFlags::WRITE),
+        0
+    );
+    buf[0] = 42;
+    assert_eq!(munmap(addr, len), 0);
+
+    // file-backed mapping
+    let test_str = "Hello, mmap!";
+    let fd = open("mmap_file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+    let fd = open("mmap_file\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let addr = mmap(
+        0,
+        PAGE_SIZE,
+        ProtFlags::READ,
+        MapFlags::PRIVATE,
+        fd as usize,
+        0,
+    );
+    close(fd as usize);
+    assert!(addr > 0);
+    let buf = unsafe { core::slice::from_raw_parts(addr as *const u8, test_str.len() + 1) };
+    assert_eq!(&buf[..test_str.len()], test_str.as_bytes());
+    assert_eq!(buf[test_str.len()], 0);
+    assert_eq!(munmap(addr as usize, PAGE_SIZE), 0);
+
+    println!("mmap_simple passed!");
+    0
+}
@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::mount;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 3 {
+        println!("usage: mount <device> <dir>, the second disk is vdb, tmpfs is in memory");
+        return -1;
+    }
+    // the arguments are followed by '\0' in memory
+    if mount(argv[1], argv[2]) == -1 {
+        println!("mount: cannot mount {} on {}", argv[1], argv[2]);
+        return -1;
+    }
+    0
+}
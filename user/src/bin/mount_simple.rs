@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, link, mkdir, mount, open, read, rename, rmdir, stat, umount, unlink, write, OpenFlags,
+    Stat,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("/mount_dir\0"), 0);
+    // vda holds the root and is mounted once
+    assert_eq!(mount("vda\0", "/mount_dir\0"), -1);
+    assert_eq!(mount("sda\0", "/mount_dir\0"), -1);
+    assert_eq!(mount("vdb\0", "/mount_none\0"), -1);
+    assert_eq!(umount("/\0"), -1);
+    assert_eq!(umount("/mount_dir\0"), -1);
+    if mount("vdb\0", "/mount_dir\0") == -1 {
+        println!("no easy-fs on vdb, run with DISK2=<image> to mount one");
+    } else {
+        let mut st = Stat::default();
+        assert_eq!(stat("/mount_dir\0", &mut st), 0);
+        assert_eq!((st.dev, st.ino), (1, 0));
+        assert_eq!(mount("vdb\0", "/\0"), -1);
+        let test_str = "Hello, mount!";
+        let fd = open(
+            "/mount_dir/mount_a\0",
+            OpenFlags::CREATE | OpenFlags::WRONLY,
+        );
+        assert!(fd > 0);
+        write(fd as usize, test_str.as_bytes());
+        // busy while a file is open
+        assert_eq!(umount("/mount_dir\0"), -1);
+        close(fd as usize);
+        // no links and moves between file systems, no removing a mount point
+        assert_eq!(link("/mount_dir/mount_a\0", "/mount_b\0"), -1);
+        assert_eq!(rename("/mount_dir/mount_a\0", "/mount_b\0"), -1);
+        assert_eq!(rmdir("/mount_dir\0"), -1);
+        assert_eq!(rename("/mount_dir\0", "/mount_moved\0"), -1);
+        assert_eq!(umount("/mount_dir\0"), 0);
+        assert_eq!(stat("/mount_dir/mount_a\0", &mut st), -1);
+        // the file is still on the disk when mounted again
+        assert_eq!(mount("vdb\0", "/mount_dir\0"), 0);
+        let fd = open("/mount_dir/mount_a\0", OpenFlags::RDONLY);
+        assert!(fd > 0);
+        let mut buf = [0u8; 32];
+        let len = read(fd as usize, &mut buf) as usize;
+        close(fd as usize);
+        assert_eq!(&buf[..len], test_str.as_bytes());
+        assert_eq!(unlink("/mount_dir/mount_a\0"), 0);
+        assert_eq!(umount("/mount_dir\0"), 0);
+    }
+    assert_eq!(rmdir("/mount_dir\0"), 0);
+    println!("mount_simple passed!");
+    0
+}
@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, set_priority, waitpid};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(set_priority(-1), -1);
+    assert_eq!(set_priority(1), -1);
+    assert_eq!(set_priority(2), 2);
+    assert_eq!(set_priority(32), 32);
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(set_priority(8), 8);
+        exit(0);
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(set_priority(16), 16);
+    println!("priority_simple passed!");
+    0
+}
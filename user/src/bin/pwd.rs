@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::getcwd;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 256];
+    let len = getcwd(&mut buf);
+    if len == -1 {
+        println!("pwd: path too long");
+        return -1;
+    }
+    println!("{}", core::str::from_utf8(&buf[..len as usize]).unwrap());
+    0
+}
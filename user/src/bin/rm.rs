@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::unlink;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: rm <file>...");
+        return -1;
+    }
+    let mut exit_code = 0;
+    // the arguments are followed by '\0' in memory
+    for file in argv[1..].iter() {
+        if unlink(file) == -1 {
+            println!("rm: cannot remove {}", file);
+            exit_code = -1;
+        }
+    }
+    exit_code
+}
@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::rmdir;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc < 2 {
+        println!("usage: rmdir <dir>...");
+        return -1;
+    }
+    let mut exit_code = 0;
+    // the arguments are followed by '\0' in memory
+    for dir in argv[1..].iter() {
+        if rmdir(dir) == -1 {
+            println!("rmdir: cannot remove {}, is it an empty directory?", dir);
+            exit_code = -1;
+        }
+    }
+    exit_code
+}
@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    exit, fork, get_time, getrusage, times, waitpid, yield_, Rusage, Tms, RUSAGE_CHILDREN,
+    RUSAGE_SELF, RUSAGE_THREAD,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut usage = Rusage::default();
+    assert_eq!(getrusage(2, &mut usage), -1);
+
+    // yielding counts as giving up the hart
+    assert_eq!(getrusage(RUSAGE_THREAD, &mut usage), 0);
+    let nvcsw = usage.nvcsw;
+    for _ in 0..3 {
+        yield_();
+    }
+    assert_eq!(getrusage(RUSAGE_THREAD, &mut usage), 0);
+    assert!(usage.nvcsw >= nvcsw + 3);
+
+    // a child spinning for 100 ms is charged to this process once waited for
+    let mut tms = Tms::default();
+    assert!(times(&mut tms) > 0);
+    assert_eq!(tms.cutime + tms.cstime, 0);
+    let pid = fork();
+    if pid == 0 {
+        let start = get_time();
+        while get_time() - start < 100 {}
+        exit(0);
+    }
+    let mut exit_code = -1;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert!(times(&mut tms) > 0);
+    // it may have shared the hart with others
+    assert!(tms.cutime + tms.cstime >= 50);
+    assert_eq!(getrusage(RUSAGE_CHILDREN, &mut usage), 0);
+    assert!(usage.utime + usage.stime >= 50);
+    // the timer interrupt preempted it at least once
+    assert!(usage.nivcsw > 0);
+    // the threads of this process are this one
+    assert_eq!(getrusage(RUSAGE_SELF, &mut usage), 0);
+    assert!(usage.nvcsw >= nvcsw + 3);
+    println!("rusage_simple passed!");
+    0
+}
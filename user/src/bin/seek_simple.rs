@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, lseek, open, pipe, pread, pwrite, read, unlink, write, OpenFlags, SEEK_CUR, SEEK_END,
+    SEEK_SET,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/seek_a\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, b"Hello, world!");
+    let mut buf = [0u8; 32];
+
+    // rewind and read it again
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    assert_eq!(read(fd, &mut buf[..5]), 5);
+    assert_eq!(&buf[..5], b"Hello");
+    assert_eq!(lseek(fd, 2, SEEK_CUR), 7);
+    assert_eq!(read(fd, &mut buf), 6);
+    assert_eq!(&buf[..6], b"world!");
+    assert_eq!(lseek(fd, -6, SEEK_END), 7);
+    write(fd, b"there");
+    assert_eq!(lseek(fd, -20, SEEK_CUR), -1);
+    assert_eq!(lseek(fd, 0, 3), -1);
+
+    // positional access does not move the offset
+    assert_eq!(pread(fd, &mut buf, 0), 13);
+    assert_eq!(&buf[..13], b"Hello, there!");
+    assert_eq!(pwrite(fd, b"J", 0), 1);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 12);
+    assert_eq!(pread(fd, &mut buf[..5], 0), 5);
+    assert_eq!(&buf[..5], b"Jello");
+    assert_eq!(pread(fd, &mut buf, 100), 0);
+    close(fd);
+
+    // every write goes to the end
+    let fd = open("/seek_a\0", OpenFlags::WRONLY | OpenFlags::APPEND) as usize;
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    write(fd, b"!!");
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 15);
+    close(fd);
+    let fd = open("/seek_a\0", OpenFlags::RDONLY) as usize;
+    assert_eq!(read(fd, &mut buf), 15);
+    assert_eq!(&buf[..15], b"Jello, there!!!");
+    assert_eq!(pwrite(fd, b"x", 0), -1);
+    close(fd);
+    assert_eq!(unlink("/seek_a\0"), 0);
+
+    // pipes are not seekable
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(lseek(pipe_fd[0], 0, SEEK_SET), -1);
+    assert_eq!(pread(pipe_fd[0], &mut buf, 0), -1);
+    assert_eq!(pwrite(pipe_fd[1], b"x", 0), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    println!("seek_simple passed!");
+    0
+}
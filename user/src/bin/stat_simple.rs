@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fstat, link, open, pipe, sleep, stat, unlink, write, OpenFlags, Stat, StatMode,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/stat_a\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let mut st = Stat::default();
+    assert_eq!(fstat(fd, &mut st), 0);
+    assert_eq!(st.mode & StatMode::FILE.bits(), StatMode::FILE.bits());
+    assert_eq!((st.size, st.blocks, st.nlink), (0, 0, 1));
+    let ctime = st.ctime;
+
+    sleep(10);
+    write(fd, &[b'a'; 1000]);
+    assert_eq!(fstat(fd, &mut st), 0);
+    assert_eq!((st.size, st.blocks), (1000, 2));
+    assert!(st.mtime > ctime);
+    let ino = st.ino;
+    close(fd);
+
+    assert_eq!(link("/stat_a\0", "/stat_b\0"), 0);
+    let mut st_b = Stat::default();
+    assert_eq!(stat("/stat_b\0", &mut st_b), 0);
+    assert_eq!((st_b.ino, st_b.nlink), (ino, 2));
+    assert_eq!(unlink("/stat_a\0"), 0);
+    assert_eq!(unlink("/stat_b\0"), 0);
+    assert_eq!(stat("/stat_a\0", &mut st), -1);
+
+    assert_eq!(stat("/\0", &mut st), 0);
+    assert!(st.is_dir());
+    assert_eq!(st.ino, 0);
+    // pipes have no inode
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(fstat(pipe_fd[0], &mut st), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    println!("stat_simple passed!");
+    0
+}
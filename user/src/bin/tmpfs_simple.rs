@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, link, mkdir, mount, open, pread, read, rename, rmdir, stat, umount, unlink, write,
+    OpenFlags, Stat,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut root_st = Stat::default();
+    let mut st = Stat::default();
+    assert_eq!(stat("/\0", &mut root_st), 0);
+    assert_eq!(stat("/tmp\0", &mut st), 0);
+    assert!(st.is_dir());
+    assert_ne!(st.dev, root_st.dev);
+
+    // larger than a page, written in chunks with the byte at offset `i` being `i as u8`
+    let mut buf = [0u8; 1000];
+    let fd = open("/tmp/tmpfs_a\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    for chunk in 0..5 {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = (chunk * 1000 + i) as u8;
+        }
+        assert_eq!(write(fd as usize, &buf), 1000);
+    }
+    close(fd as usize);
+    assert_eq!(stat("/tmp/tmpfs_a\0", &mut st), 0);
+    assert_eq!(st.size, 5000);
+    let fd = open("/tmp/tmpfs_a\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    for chunk in 0..5 {
+        assert_eq!(read(fd as usize, &mut buf), 1000);
+        for (i, byte) in buf.iter().enumerate() {
+            assert_eq!(*byte, (chunk * 1000 + i) as u8);
+        }
+    }
+    assert_eq!(read(fd as usize, &mut buf), 0);
+    assert_eq!(pread(fd as usize, &mut buf[..8], 4094), 8);
+    for (i, byte) in buf[..8].iter().enumerate() {
+        assert_eq!(*byte, (4094 + i) as u8);
+    }
+    close(fd as usize);
+    let fd = open("/tmp/tmpfs_a\0", OpenFlags::TRUNC | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(stat("/tmp/tmpfs_a\0", &mut st), 0);
+    assert_eq!(st.size, 0);
+
+    // directories, links and moves stay within the tmpfs
+    assert_eq!(mkdir("/tmp/tmpfs_dir\0"), 0);
+    assert_eq!(rename("/tmp/tmpfs_a\0", "/tmp/tmpfs_dir/tmpfs_b\0"), 0);
+    assert_eq!(link("/tmp/tmpfs_dir/tmpfs_b\0", "/tmp/tmpfs_c\0"), 0);
+    assert_eq!(stat("/tmp/tmpfs_c\0", &mut st), 0);
+    assert_eq!(st.nlink, 2);
+    assert_eq!(link("/tmp/tmpfs_c\0", "/tmpfs_d\0"), -1);
+    assert_eq!(rename("/tmp/tmpfs_dir\0", "/tmp/tmpfs_dir/sub\0"), -1);
+    assert_eq!(rmdir("/tmp/tmpfs_dir\0"), -1);
+    assert_eq!(unlink("/tmp/tmpfs_dir/tmpfs_b\0"), 0);
+    assert_eq!(unlink("/tmp/tmpfs_c\0"), 0);
+    assert_eq!(stat("/tmp/tmpfs_c\0", &mut st), -1);
+
+    // another tmpfs starts empty and is gone once unmounted
+    assert_eq!(mount("tmpfs\0", "/tmp/tmpfs_dir\0"), 0);
+    let fd = open(
+        "/tmp/tmpfs_dir/tmpfs_e\0",
+        OpenFlags::CREATE | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    assert_eq!(umount("/tmp/tmpfs_dir\0"), -1);
+    close(fd as usize);
+    assert_eq!(umount("/tmp/tmpfs_dir\0"), 0);
+    assert_eq!(stat("/tmp/tmpfs_dir/tmpfs_e\0", &mut st), -1);
+    assert_eq!(rmdir("/tmp/tmpfs_dir\0"), 0);
+    println!("tmpfs_simple passed!");
+    0
+}
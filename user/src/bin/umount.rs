@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::umount;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 2 {
+        println!("usage: umount <dir>");
+        return -1;
+    }
+    // the arguments are followed by '\0' in memory
+    if umount(argv[1]) == -1 {
+        println!("umount: cannot unmount {}, is a file on it open?", argv[1]);
+        return -1;
+    }
+    0
+}
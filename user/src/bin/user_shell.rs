@@ -16,7 +16,7 @@ const LINE_START: &str = ">> ";
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{chdir, close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
 
 #[derive(Debug)]
 struct ProcessArguments {
@@ -84,7 +84,18 @@ pub fn main() -> i32 {
         match c {
             LF | CR => {
                 println!("");
-                if !line.is_empty() {
+                if line == "cd" || line.starts_with("cd ") {
+                    // the working directory belongs to the shell itself
+                    let mut dir = String::from(line[2..].trim());
+                    if dir.is_empty() {
+                        dir.push('/');
+                    }
+                    dir.push('\0');
+                    if chdir(dir.as_str()) == -1 {
+                        println!("cd: no such directory");
+                    }
+                    line.clear();
+                } else if !line.is_empty() {
                     let splited: Vec<_> = line.as_str().split('|').collect();
                     let process_arguments_list: Vec<_> = splited
                         .iter()
@@ -172,8 +183,12 @@ pub fn main() -> i32 {
                                     close(pipe_fd[0]);
                                     close(pipe_fd[1]);
                                 }
-                                // execute new application
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
+                                // execute new application, bare names are looked up in "/"
+                                let mut app_path = args_copy[0].clone();
+                                if !app_path.contains('/') {
+                                    app_path.insert(0, '/');
+                                }
+                                if exec(app_path.as_str(), args_addr.as_slice()) == -1 {
                                     println!("Error when executing!");
                                     return -4;
                                 }
@@ -10,8 +10,9 @@ extern crate user_lib;
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("filetest_simple\0", "\0", "\0", "\0", 0),
-    ("cat\0", "filea\0", "\0", "\0", 0),
+    ("cat\0", "/tmp/filea\0", "\0", "\0", 0),
     ("cmdline_args\0", "1\0", "2\0", "3\0", 0),
+    ("dir_simple\0", "\0", "\0", "\0", 0),
     ("eisenberg\0", "\0", "\0", "\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
     ("fantastic_text\0", "\0", "\0", "\0", 0),
@@ -19,20 +20,30 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("forktest\0", "\0", "\0", "\0", 0),
     ("forktest2\0", "\0", "\0", "\0", 0),
     ("forktree\0", "\0", "\0", "\0", 0),
+    ("heap_grow\0", "\0", "\0", "\0", 0),
     ("hello_world\0", "\0", "\0", "\0", 0),
+    ("link_simple\0", "\0", "\0", "\0", 0),
     ("huge_write\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
+    ("mmap_simple\0", "\0", "\0", "\0", 0),
+    ("mount_simple\0", "\0", "\0", "\0", 0),
     ("mpsc_sem\0", "\0", "\0", "\0", 0),
     ("peterson\0", "\0", "\0", "\0", 0),
     ("phil_din_mutex\0", "\0", "\0", "\0", 0),
     ("pipe_large_test\0", "\0", "\0", "\0", 0),
     ("pipetest\0", "\0", "\0", "\0", 0),
+    ("priority_simple\0", "\0", "\0", "\0", 0),
+    ("rusage_simple\0", "\0", "\0", "\0", 0),
     ("adder_peterson_spin\0", "\0", "\0", "\0", 0),
     ("adder_peterson_yield\0", "\0", "\0", "\0", 0),
     ("adder_mutex_blocking\0", "\0", "\0", "\0", 0),
     ("adder_mutex_spin\0", "\0", "\0", "\0", 0),
     ("run_pipe_test\0", "\0", "\0", "\0", 0),
+    ("seek_simple\0", "\0", "\0", "\0", 0),
+    ("getdents_simple\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
+    ("stat_simple\0", "\0", "\0", "\0", 0),
+    ("tmpfs_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sync_sem\0", "\0", "\0", "\0", 0),
@@ -1,4 +1,5 @@
 use super::*;
+use core::convert::TryInto;
 
 bitflags! {
     pub struct OpenFlags: u32 {
@@ -7,9 +8,96 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 9;
         const TRUNC = 1 << 10;
+        const APPEND = 1 << 11;
     }
 }
 
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// File status filled by `fstat` and `stat`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Stat {
+    /// ID of the device containing the file
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// file type and permission bits
+    pub mode: u32,
+    /// number of hard links
+    pub nlink: u32,
+    pub size: u64,
+    /// number of 512B blocks in use
+    pub blocks: u64,
+    /// last modification time in ms
+    pub mtime: u64,
+    /// last status change time in ms
+    pub ctime: u64,
+}
+
+bitflags! {
+    /// File type bits of `Stat::mode`.
+    pub struct StatMode: u32 {
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+    }
+}
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        self.mode & StatMode::DIR.bits == StatMode::DIR.bits
+    }
+}
+
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+
+/// A directory record read by `getdents`.
+pub struct Dirent<'a> {
+    /// inode number
+    pub ino: u64,
+    /// one of `DT_DIR` and `DT_REG`
+    pub type_: u8,
+    pub name: &'a str,
+}
+
+/// Iterate the records `getdents` has read into `buf`.
+/// Offset of the name in a record, the fields before it are not padded.
+const DIRENT_NAME_OFFSET: usize = 19;
+
+pub struct DirentIter<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> DirentIter<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+}
+
+impl<'a> Iterator for DirentIter<'a> {
+    type Item = Dirent<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        // ino: u64, off: u64, reclen: u16, type: u8, then the name ending with '\0'
+        if self.buf.len() < DIRENT_NAME_OFFSET {
+            return None;
+        }
+        let ino = u64::from_le_bytes(self.buf[0..8].try_into().unwrap());
+        let reclen = u16::from_le_bytes(self.buf[16..18].try_into().unwrap()) as usize;
+        let type_ = self.buf[18];
+        let name = &self.buf[DIRENT_NAME_OFFSET..reclen];
+        let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        let name = core::str::from_utf8(&name[..name_len]).unwrap();
+        self.buf = &self.buf[reclen..];
+        Some(Dirent { ino, type_, name })
+    }
+}
+
+pub const AT_FDCWD: isize = -100;
+pub const AT_REMOVEDIR: u32 = 0x200;
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
@@ -28,3 +116,55 @@ pub fn read(fd: usize, buf: &mut [u8]) -> isize {
 pub fn write(fd: usize, buf: &[u8]) -> isize {
     sys_write(fd, buf)
 }
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdir(path)
+}
+pub fn unlink(path: &str) -> isize {
+    sys_unlinkat(AT_FDCWD, path, 0)
+}
+pub fn rmdir(path: &str) -> isize {
+    sys_unlinkat(AT_FDCWD, path, AT_REMOVEDIR)
+}
+pub fn link(old_path: &str, new_path: &str) -> isize {
+    sys_linkat(AT_FDCWD, old_path, AT_FDCWD, new_path, 0)
+}
+pub fn rename(old_path: &str, new_path: &str) -> isize {
+    sys_renameat(AT_FDCWD, old_path, AT_FDCWD, new_path)
+}
+/// Read directory records of the directory `fd` into `buf`, parse them with `DirentIter`.
+/// Return 0 at the end of the directory.
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+    sys_getdents64(fd, buf)
+}
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+pub fn pread(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    sys_pread(fd, buf, offset)
+}
+pub fn pwrite(fd: usize, buf: &[u8], offset: usize) -> isize {
+    sys_pwrite(fd, buf, offset)
+}
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+    sys_fstat(fd, st)
+}
+pub fn sync() -> isize {
+    sys_sync()
+}
+pub fn stat(path: &str, st: &mut Stat) -> isize {
+    sys_stat(path, st)
+}
+/// Mount the block device `source`, `vda` holds the root and `vdb` is the next one,
+/// or a new file system in memory if `source` is `tmpfs`.
+pub fn mount(source: &str, target: &str) -> isize {
+    sys_mount(source, target)
+}
+pub fn umount(target: &str) -> isize {
+    sys_umount(target)
+}
+pub fn chdir(path: &str) -> isize {
+    sys_chdir(path)
+}
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    sys_getcwd(buf)
+}
@@ -0,0 +1,41 @@
+use super::*;
+
+bitflags! {
+    pub struct ProtFlags: u32 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MapFlags: u32 {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+pub fn mmap(
+    addr: usize,
+    len: usize,
+    prot: ProtFlags,
+    flags: MapFlags,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    sys_mmap(addr, len, prot.bits, flags.bits, fd, offset)
+}
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+pub fn mprotect(addr: usize, len: usize, prot: ProtFlags) -> isize {
+    sys_mprotect(addr, len, prot.bits)
+}
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+pub fn sbrk(increment: isize) -> isize {
+    sys_sbrk(increment)
+}
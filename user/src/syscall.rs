@@ -1,20 +1,44 @@
+use super::{Rusage, Stat, Tms};
+
+const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 24;
 const SYSCALL_CONNECT: usize = 29;
 const SYSCALL_LISTEN: usize = 30;
 const SYSCALL_ACCEPT: usize = 31;
+const SYSCALL_MKDIR: usize = 34;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_RENAMEAT: usize = 38;
+const SYSCALL_UMOUNT: usize = 39;
+const SYSCALL_MOUNT: usize = 40;
+const SYSCALL_CHDIR: usize = 49;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
+const SYSCALL_GETDENTS64: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PREAD: usize = 67;
+const SYSCALL_PWRITE: usize = 68;
+const SYSCALL_STAT: usize = 79;
+const SYSCALL_FSTAT: usize = 80;
+const SYSCALL_SYNC: usize = 81;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_SLEEP: usize = 101;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_TIMES: usize = 153;
+const SYSCALL_GETRUSAGE: usize = 165;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
@@ -32,6 +56,7 @@ const SYSCALL_FRAMEBUFFER: usize = 2000;
 const SYSCALL_FRAMEBUFFER_FLUSH: usize = 2001;
 const SYSCALL_EVENT_GET: usize = 3000;
 const SYSCALL_KEY_PRESSED: usize = 3001;
+const SYSCALL_SBRK: usize = 4000;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -47,6 +72,87 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") args[0] => ret,
+            in("x11") args[1],
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x15") args[5],
+            in("x17") id
+        );
+    }
+    ret
+}
+
+pub fn sys_getcwd(buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_GETCWD, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
+pub fn sys_mkdir(path: &str) -> isize {
+    syscall(SYSCALL_MKDIR, [path.as_ptr() as usize, 0, 0])
+}
+
+pub fn sys_unlinkat(dirfd: isize, path: &str, flags: u32) -> isize {
+    syscall(
+        SYSCALL_UNLINKAT,
+        [dirfd as usize, path.as_ptr() as usize, flags as usize],
+    )
+}
+
+pub fn sys_linkat(
+    old_dirfd: isize,
+    old_path: &str,
+    new_dirfd: isize,
+    new_path: &str,
+    flags: u32,
+) -> isize {
+    syscall6(
+        SYSCALL_LINKAT,
+        [
+            old_dirfd as usize,
+            old_path.as_ptr() as usize,
+            new_dirfd as usize,
+            new_path.as_ptr() as usize,
+            flags as usize,
+            0,
+        ],
+    )
+}
+
+pub fn sys_renameat(old_dirfd: isize, old_path: &str, new_dirfd: isize, new_path: &str) -> isize {
+    syscall6(
+        SYSCALL_RENAMEAT,
+        [
+            old_dirfd as usize,
+            old_path.as_ptr() as usize,
+            new_dirfd as usize,
+            new_path.as_ptr() as usize,
+            0,
+            0,
+        ],
+    )
+}
+
+pub fn sys_umount(target: &str) -> isize {
+    syscall(SYSCALL_UMOUNT, [target.as_ptr() as usize, 0, 0])
+}
+
+pub fn sys_mount(source: &str, target: &str) -> isize {
+    syscall(
+        SYSCALL_MOUNT,
+        [source.as_ptr() as usize, target.as_ptr() as usize, 0],
+    )
+}
+
+pub fn sys_chdir(path: &str) -> isize {
+    syscall(SYSCALL_CHDIR, [path.as_ptr() as usize, 0, 0])
+}
+
 pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0])
 }
@@ -90,6 +196,46 @@ pub fn sys_write(fd: usize, buffer: &[u8]) -> isize {
     syscall(SYSCALL_WRITE, [fd, buffer.as_ptr() as usize, buffer.len()])
 }
 
+pub fn sys_getdents64(fd: usize, buffer: &mut [u8]) -> isize {
+    syscall(
+        SYSCALL_GETDENTS64,
+        [fd, buffer.as_mut_ptr() as usize, buffer.len()],
+    )
+}
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    syscall(SYSCALL_LSEEK, [fd, offset as usize, whence])
+}
+
+pub fn sys_pread(fd: usize, buffer: &mut [u8], offset: usize) -> isize {
+    syscall6(
+        SYSCALL_PREAD,
+        [fd, buffer.as_mut_ptr() as usize, buffer.len(), offset, 0, 0],
+    )
+}
+
+pub fn sys_pwrite(fd: usize, buffer: &[u8], offset: usize) -> isize {
+    syscall6(
+        SYSCALL_PWRITE,
+        [fd, buffer.as_ptr() as usize, buffer.len(), offset, 0, 0],
+    )
+}
+
+pub fn sys_stat(path: &str, st: &mut Stat) -> isize {
+    syscall(
+        SYSCALL_STAT,
+        [path.as_ptr() as usize, st as *mut Stat as usize, 0],
+    )
+}
+
+pub fn sys_fstat(fd: usize, st: &mut Stat) -> isize {
+    syscall(SYSCALL_FSTAT, [fd, st as *mut Stat as usize, 0])
+}
+
+pub fn sys_sync() -> isize {
+    syscall(SYSCALL_SYNC, [0, 0, 0])
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     syscall(SYSCALL_EXIT, [exit_code as usize, 0, 0]);
     panic!("sys_exit never returns!");
@@ -107,6 +253,21 @@ pub fn sys_kill(pid: usize, signal: i32) -> isize {
     syscall(SYSCALL_KILL, [pid, signal as usize, 0])
 }
 
+pub fn sys_set_priority(prio: isize) -> isize {
+    syscall(SYSCALL_SET_PRIORITY, [prio as usize, 0, 0])
+}
+
+pub fn sys_times(tms: &mut Tms) -> isize {
+    syscall(SYSCALL_TIMES, [tms as *mut Tms as usize, 0, 0])
+}
+
+pub fn sys_getrusage(who: isize, usage: &mut Rusage) -> isize {
+    syscall(
+        SYSCALL_GETRUSAGE,
+        [who as usize, usage as *mut Rusage as usize, 0],
+    )
+}
+
 pub fn sys_get_time() -> isize {
     syscall(SYSCALL_GET_TIME, [0, 0, 0])
 }
@@ -193,3 +354,26 @@ pub fn sys_event_get() -> isize {
 pub fn sys_key_pressed() -> isize {
     syscall(SYSCALL_KEY_PRESSED, [0, 0, 0])
 }
+
+pub fn sys_mmap(addr: usize, len: usize, prot: u32, flags: u32, fd: usize, offset: usize) -> isize {
+    syscall6(
+        SYSCALL_MMAP,
+        [addr, len, prot as usize, flags as usize, fd, offset],
+    )
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [addr, len, 0])
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: u32) -> isize {
+    syscall(SYSCALL_MPROTECT, [addr, len, prot as usize])
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_sbrk(increment: isize) -> isize {
+    syscall(SYSCALL_SBRK, [increment as usize, 0, 0])
+}
@@ -6,6 +6,20 @@ pub fn exit(exit_code: i32) -> ! {
 pub fn yield_() -> isize {
     sys_yield()
 }
+/// Set the priority of the current thread, 16 by default, at least 2 and at most 1 << 20.
+/// A larger one runs more with stride scheduling and first with static priorities.
+pub fn set_priority(prio: isize) -> isize {
+    sys_set_priority(prio)
+}
+/// Fill `tms` with the CPU time of this process and of the children it waited for,
+/// return the time since boot, all in ms.
+pub fn times(tms: &mut Tms) -> isize {
+    sys_times(tms)
+}
+/// Fill `usage` for `who`, one of `RUSAGE_SELF`, `RUSAGE_CHILDREN` and `RUSAGE_THREAD`.
+pub fn getrusage(who: isize, usage: &mut Rusage) -> isize {
+    sys_getrusage(who, usage)
+}
 pub fn get_time() -> isize {
     sys_get_time()
 }
@@ -47,6 +61,34 @@ pub fn waitpid_nb(pid: usize, exit_code: &mut i32) -> isize {
     sys_waitpid(pid as isize, exit_code as *mut _)
 }
 
+/// Filled by `times`, in ms.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Tms {
+    pub utime: usize,
+    pub stime: usize,
+    /// user time of the children waited for and of theirs
+    pub cutime: usize,
+    /// system time of the children waited for and of theirs
+    pub cstime: usize,
+}
+
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+pub const RUSAGE_THREAD: isize = 1;
+
+/// Filled by `getrusage`, times in ms.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Rusage {
+    pub utime: usize,
+    pub stime: usize,
+    /// voluntary context switches
+    pub nvcsw: usize,
+    /// involuntary context switches
+    pub nivcsw: usize,
+}
+
 bitflags! {
     pub struct SignalFlags: i32 {
         const SIGINT    = 1 << 2;